@@ -0,0 +1,266 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::de::{self, Deserializer};
+
+use crate::redact::Mode;
+use crate::rules::{RuleDef, Rules};
+use crate::scrub::FieldPolicy;
+
+/// Name of the config file looked for next to the input and in its parents.
+pub const CONFIG_FILE_NAME: &str = ".utracy-redact.toml";
+
+/// Settings from a `.utracy-redact.toml` file.
+///
+/// Keys mirror the command-line options (`file-marker = ["code_secret"]`,
+/// `mode = "merge"`, ...), plus `[[rule]]` tables as in a rules file.
+/// Relative paths are resolved against the directory holding the config.
+#[derive(Debug)]
+pub struct Config {
+    /// Where the config was loaded from.
+    pub path: PathBuf,
+    /// Its `[[rule]]` tables.
+    pub rules: Rules,
+    pub settings: Settings,
+}
+
+/// The option keys of a [`Config`]. Unset keys are `None` or empty.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    #[serde(rename = "rules")]
+    pub rules_files: Vec<PathBuf>,
+    pub preset: Vec<String>,
+    pub codebase: Option<PathBuf>,
+    pub annotations: Option<PathBuf>,
+    pub file_marker: Vec<String>,
+    pub fn_marker: Vec<String>,
+    pub name_marker: Vec<String>,
+    pub file_glob: Vec<String>,
+    pub file_regex: Vec<String>,
+    pub fn_regex: Vec<String>,
+    pub name_regex: Vec<String>,
+    pub line_range: Vec<String>,
+    pub default_deny: Option<bool>,
+    pub public_root: Vec<String>,
+    pub public_tree: Option<PathBuf>,
+    pub keep_file: Vec<String>,
+    pub keep_fn: Vec<String>,
+    pub keep_name: Vec<String>,
+    #[serde(deserialize_with = "parsed")]
+    pub mode: Option<Mode>,
+    pub pseudonym_key: Option<PathBuf>,
+    pub pseudonymize: Option<bool>,
+    #[serde(deserialize_with = "parsed_vec")]
+    pub sidecar_recipient: Vec<age::x25519::Recipient>,
+    pub drop_zones: Option<bool>,
+    pub drop_descendants: Option<bool>,
+    #[serde(deserialize_with = "parsed")]
+    pub redacted_line: Option<FieldPolicy<u32>>,
+    #[serde(deserialize_with = "parsed")]
+    pub redacted_color: Option<FieldPolicy<u32>>,
+    pub scrub_header: Option<bool>,
+    #[serde(deserialize_with = "parsed")]
+    pub host_info: Option<FieldPolicy<String>>,
+    #[serde(deserialize_with = "parsed")]
+    pub program_name: Option<FieldPolicy<String>>,
+    #[serde(deserialize_with = "parsed")]
+    pub process_id: Option<FieldPolicy<i64>>,
+    #[serde(deserialize_with = "parsed")]
+    pub epoch: Option<FieldPolicy<i64>>,
+    #[serde(deserialize_with = "parsed")]
+    pub exec_time: Option<FieldPolicy<i64>>,
+    /// `[[rule]]` tables, moved into [`Config::rules`] on load.
+    #[serde(rename = "rule")]
+    rule_defs: Vec<RuleDef>,
+}
+
+impl Settings {
+    /// Layer `under` beneath these settings: keys set here win, lists are
+    /// appended to.
+    pub fn layer(&mut self, under: Settings) {
+        self.rules_files.extend(under.rules_files);
+        self.preset.extend(under.preset);
+        self.codebase = self.codebase.take().or(under.codebase);
+        self.annotations = self.annotations.take().or(under.annotations);
+        self.file_marker.extend(under.file_marker);
+        self.fn_marker.extend(under.fn_marker);
+        self.name_marker.extend(under.name_marker);
+        self.file_glob.extend(under.file_glob);
+        self.file_regex.extend(under.file_regex);
+        self.fn_regex.extend(under.fn_regex);
+        self.name_regex.extend(under.name_regex);
+        self.line_range.extend(under.line_range);
+        self.default_deny = self.default_deny.or(under.default_deny);
+        self.public_root.extend(under.public_root);
+        self.public_tree = self.public_tree.take().or(under.public_tree);
+        self.keep_file.extend(under.keep_file);
+        self.keep_fn.extend(under.keep_fn);
+        self.keep_name.extend(under.keep_name);
+        if self.sidecar_recipient.is_empty() {
+            self.sidecar_recipient = under.sidecar_recipient;
+        }
+        self.mode = self.mode.or(under.mode);
+        self.pseudonym_key = self.pseudonym_key.take().or(under.pseudonym_key);
+        self.pseudonymize = self.pseudonymize.or(under.pseudonymize);
+        self.drop_zones = self.drop_zones.or(under.drop_zones);
+        self.drop_descendants = self.drop_descendants.or(under.drop_descendants);
+        self.redacted_line = self.redacted_line.take().or(under.redacted_line);
+        self.redacted_color = self.redacted_color.take().or(under.redacted_color);
+        self.scrub_header = self.scrub_header.or(under.scrub_header);
+        self.host_info = self.host_info.take().or(under.host_info);
+        self.program_name = self.program_name.take().or(under.program_name);
+        self.process_id = self.process_id.take().or(under.process_id);
+        self.epoch = self.epoch.take().or(under.epoch);
+        self.exec_time = self.exec_time.take().or(under.exec_time);
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("reading config file: {}", path.display()))?;
+        let origin = path.display().to_string();
+        let mut settings: Settings =
+            toml::from_str(&text).with_context(|| format!("loading config file: {origin}"))?;
+        let rules = Rules::from_defs(std::mem::take(&mut settings.rule_defs), &origin)
+            .with_context(|| format!("loading config file: {origin}"))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for p in settings
+            .rules_files
+            .iter_mut()
+            .chain(settings.codebase.as_mut())
+            .chain(settings.annotations.as_mut())
+            .chain(settings.public_tree.as_mut())
+            .chain(settings.pseudonym_key.as_mut())
+        {
+            *p = dir.join(&*p);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rules,
+            settings,
+        })
+    }
+
+    /// Find and load the configs that apply to `input`, nearest first: a
+    /// [`CONFIG_FILE_NAME`] next to it or in any parent directory, then
+    /// `utracy-redact/config.toml` in the user config directory.
+    pub fn discover(input: &Path) -> Result<Vec<Self>> {
+        let input = fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+        let mut paths: Vec<_> = input
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .collect();
+        if let Some(dir) = dirs::config_dir() {
+            paths.push(dir.join("utracy-redact").join("config.toml"));
+        }
+        paths
+            .into_iter()
+            .filter(|p| p.is_file())
+            .map(|p| Self::load(&p))
+            .collect()
+    }
+}
+
+/// Deserialize an optional value through its [`FromStr`] impl.
+fn parsed<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Option::<String>::deserialize(d)?
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+/// Deserialize a list of values through their [`FromStr`] impl.
+fn parsed_vec<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(text: &str) -> Settings {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn nearer_settings_win() {
+        let mut near = settings(
+            r#"
+            mode = "merge"
+            fn-marker = ["near"]
+            drop-zones = false
+            "#,
+        );
+        near.layer(settings(
+            r#"
+            mode = "drop"
+            fn-marker = ["far"]
+            drop-zones = true
+            scrub-header = true
+            "#,
+        ));
+        assert_eq!(near.mode, Some(Mode::Merge));
+        assert_eq!(near.fn_marker, ["near", "far"]);
+        assert_eq!(near.drop_zones, Some(false));
+        assert_eq!(near.scrub_header, Some(true));
+        assert_eq!(near.default_deny, None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Settings>("fn-markers = [\"typo\"]").is_err());
+    }
+
+    #[test]
+    fn load_resolves_paths_against_its_directory() {
+        let dir = std::env::temp_dir().join(format!("utracy-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE_NAME);
+        fs::write(
+            &path,
+            r#"
+            rules = ["rules/redact.toml"]
+            codebase = "../goonstation"
+            pseudonym-key = "key.bin"
+            public-tree = "/abs/public"
+
+            [[rule]]
+            action = "drop"
+            file = "glob:code/modules/admin/**"
+            "#,
+        )
+        .unwrap();
+        let config = Config::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let settings = config.settings;
+        assert_eq!(settings.rules_files, [dir.join("rules/redact.toml")]);
+        assert_eq!(settings.codebase, Some(dir.join("../goonstation")));
+        assert_eq!(settings.pseudonym_key, Some(dir.join("key.bin")));
+        assert_eq!(settings.public_tree, Some(PathBuf::from("/abs/public")));
+        assert_eq!(config.rules.iter().count(), 1);
+    }
+}
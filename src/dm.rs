@@ -0,0 +1,735 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use walkdir::WalkDir;
+
+use crate::markers::{Field, Marker, Pattern, fold_case};
+use crate::rules::{Action, Condition, Rule, Rules};
+
+/// Comment marking the proc defined on the next line as secret, written as
+/// `// utracy:redact`.
+pub const REDACT_ANNOTATION: &str = "utracy:redact";
+
+/// A proc or verb defined in DM source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcDef {
+    /// File the proc is defined in, relative to the `.dme`, `/`-separated.
+    pub file: String,
+    /// Full proc path as defined, e.g. `/datum/foo/proc/bar`, or
+    /// `/datum/foo/bar` for an override.
+    pub path: String,
+    /// 1-based line of the definition.
+    pub line: u32,
+}
+
+/// The files and procs a DM codebase keeps in its git submodules, found by
+/// following the `.dme` includes.
+#[derive(Debug, Clone)]
+pub struct Codebase {
+    /// The environment file the includes were read from.
+    pub dme: PathBuf,
+    /// Submodule paths from `.gitmodules`, relative to the `.dme`.
+    pub submodules: Vec<String>,
+    /// Included files inside a submodule, relative to the `.dme`.
+    pub secret_files: Vec<String>,
+    /// Procs and verbs defined in [`Codebase::secret_files`].
+    pub secret_procs: Vec<ProcDef>,
+    /// Included files that aren't in the checkout (e.g. a submodule that
+    /// wasn't cloned), so their procs are unknown.
+    pub missing: Vec<String>,
+}
+
+impl Codebase {
+    /// Scan the codebase at `path`: a `.dme` file, or a directory holding
+    /// exactly one.
+    pub fn scan(path: &Path) -> Result<Self> {
+        let dme = find_dme(path)?;
+        let root = dme.parent().unwrap_or_else(|| Path::new("."));
+
+        let submodules = read_gitmodules(root)?;
+
+        let mut codebase = Self {
+            dme: dme.clone(),
+            submodules,
+            secret_files: Vec::new(),
+            secret_procs: Vec::new(),
+            missing: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        let mut queue = vec![String::new()];
+        while let Some(file) = queue.pop() {
+            // The empty path stands for the .dme itself.
+            let disk = if file.is_empty() {
+                dme.clone()
+            } else {
+                root.join(&file)
+            };
+            let secret = codebase.is_secret(&file);
+            let bytes = match fs::read(&disk) {
+                Ok(bytes) => bytes,
+                Err(e) if file.is_empty() => {
+                    return Err(e).with_context(|| format!("reading {}", dme.display()));
+                }
+                Err(_) => {
+                    if secret {
+                        codebase.missing.push(file);
+                    }
+                    continue;
+                }
+            };
+            let source = String::from_utf8_lossy(&bytes);
+
+            if secret {
+                codebase.secret_procs.extend(parse_procs(&file, &source));
+            }
+            let dir = file.rsplit_once('/').map_or("", |(dir, _)| dir);
+            let mut includes = Vec::new();
+            for include in parse_includes(&source) {
+                let include = join_path(dir, &include);
+                if !seen.insert(include.clone()) || !is_source(&include) {
+                    continue;
+                }
+                if codebase.is_secret(&include) {
+                    codebase.secret_files.push(include.clone());
+                }
+                includes.push(include);
+            }
+            // Reversed, so they're popped in file order.
+            queue.extend(includes.into_iter().rev());
+        }
+        Ok(codebase)
+    }
+
+    fn is_secret(&self, file: &str) -> bool {
+        let file = fold_case(file);
+        self.submodules.iter().any(|m| {
+            file.strip_prefix(&fold_case(m))
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    /// Redact rules for every secret file and proc.
+    pub fn rules(&self) -> Rules {
+        let origin = self.dme.file_name().map_or_else(
+            || self.dme.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        let mut rules = Rules::new();
+        let mut push = |marker: Marker| {
+            rules.push(Rule {
+                label: format!("{marker} from {origin}"),
+                conditions: vec![Condition::Field(marker)],
+                action: Action::Redact,
+            });
+        };
+        // One set lookup each, so large codebases don't cost a rule per file
+        // and proc.
+        if !self.secret_files.is_empty() {
+            push(Marker::new(
+                Field::File,
+                Pattern::one_of(&self.secret_files),
+            ));
+        }
+        if !self.secret_procs.is_empty() {
+            let paths = self.secret_procs.iter().map(|def| &def.path);
+            push(Marker::new(
+                Field::Function,
+                Pattern::one_of_with(paths, true),
+            ));
+        }
+        rules
+    }
+}
+
+/// Procs marked secret in the source with a [`REDACT_ANNOTATION`].
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    pub procs: Vec<ProcDef>,
+    /// `(file, line)` of annotations not directly above a proc definition.
+    pub dangling: Vec<(String, u32)>,
+}
+
+impl Annotations {
+    /// Scan every `.dm` file under `root`, a codebase directory or the
+    /// `.dme` in it.
+    pub fn scan(root: &Path) -> Result<Self> {
+        let root = if root.is_file() {
+            root.parent().unwrap_or_else(|| Path::new("."))
+        } else {
+            root
+        };
+        let mut annotations = Self::default();
+        for file in index_tree(root)? {
+            let path = root.join(&file);
+            let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            let source = String::from_utf8_lossy(&bytes);
+            // Cheap check first; most files have no annotations.
+            if !source.contains(REDACT_ANNOTATION) {
+                continue;
+            }
+            let (procs, dangling) = parse_annotations(&file, &source);
+            annotations.procs.extend(procs);
+            annotations
+                .dangling
+                .extend(dangling.into_iter().map(|line| (file.clone(), line)));
+        }
+        Ok(annotations)
+    }
+
+    /// A redact rule per annotated proc, matching srclocs from its file with
+    /// its proc path or definition line. Overrides are also matched as
+    /// `/type/proc/name`, however the profiler names them.
+    pub fn rules(&self) -> Rules {
+        let mut rules = Rules::new();
+        for def in &self.procs {
+            let mut names = vec![def.path.clone()];
+            if let Some((parent, name)) = def.path.rsplit_once('/')
+                && !parent.ends_with("/proc")
+                && !parent.ends_with("/verb")
+            {
+                names.push(format!("{parent}/proc/{name}"));
+            }
+            rules.push(Rule {
+                conditions: vec![
+                    Condition::Field(Marker::new(Field::File, Pattern::one_of([&def.file]))),
+                    Condition::Any(vec![
+                        Condition::Field(Marker::new(Field::Function, Pattern::one_of(names))),
+                        Condition::Line(def.line..=def.line),
+                    ]),
+                ],
+                action: Action::Redact,
+                label: format!("annotation on {} at {}:{}", def.path, def.file, def.line),
+            });
+        }
+        rules
+    }
+}
+
+/// Every `.dm` file under `root`, relative to it and `/`-separated, the way
+/// srclocs name them. Hidden directories such as `.git` are skipped.
+pub fn index_tree(root: &Path) -> Result<Vec<String>> {
+    walk_dm_files(root, &[])
+}
+
+/// Like [`index_tree`], but also skipping the git submodules listed in
+/// `root/.gitmodules`, so a checkout with private submodules cloned only
+/// lists its public files.
+pub fn index_public_tree(root: &Path) -> Result<Vec<String>> {
+    walk_dm_files(root, &read_gitmodules(root)?)
+}
+
+fn walk_dm_files(root: &Path, skip: &[String]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root).into_iter().filter_entry(|e| {
+        if e.depth() == 0 {
+            return true;
+        }
+        if e.file_name().to_string_lossy().starts_with('.') {
+            return false;
+        }
+        let relative = relative_path(root, e.path());
+        !skip.iter().any(|m| m.eq_ignore_ascii_case(&relative))
+    });
+    for entry in walker {
+        let entry = entry.with_context(|| format!("indexing {}", root.display()))?;
+        let path = entry.path();
+        if !entry.file_type().is_file()
+            || !path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("dm"))
+        {
+            continue;
+        }
+        files.push(relative_path(root, path));
+    }
+    Ok(files)
+}
+
+/// `path` relative to `root`, `/`-separated.
+fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
+
+/// The submodule paths in `root/.gitmodules`, or none if it doesn't exist.
+fn read_gitmodules(root: &Path) -> Result<Vec<String>> {
+    let gitmodules = root.join(".gitmodules");
+    if !gitmodules.is_file() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(&gitmodules)
+        .with_context(|| format!("reading {}", gitmodules.display()))?;
+    Ok(parse_gitmodules(&text))
+}
+
+/// `path` if it is a `.dme`, or the single `.dme` in directory `path`.
+fn find_dme(path: &Path) -> Result<PathBuf> {
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let entries =
+        fs::read_dir(path).with_context(|| format!("reading codebase: {}", path.display()))?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry_path = entry?.path();
+        if entry_path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("dme"))
+        {
+            found.push(entry_path);
+        }
+    }
+    match found.len() {
+        0 => bail!("no .dme file in {}", path.display()),
+        1 => Ok(found.remove(0)),
+        _ => bail!(
+            "several .dme files in {}; pass the one to use instead",
+            path.display()
+        ),
+    }
+}
+
+/// Whether an included file can define procs or include others.
+fn is_source(file: &str) -> bool {
+    let lower = file.to_ascii_lowercase();
+    lower.ends_with(".dm") || lower.ends_with(".dme")
+}
+
+/// Resolve `include` against directory `dir`, both `/`-separated, folding
+/// `.` and `..` components.
+fn join_path(dir: &str, include: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in include.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Submodule paths from a `.gitmodules` file, `/`-separated.
+pub fn parse_gitmodules(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "path").then(|| {
+                value
+                    .trim()
+                    .replace('\\', "/")
+                    .trim_end_matches('/')
+                    .to_owned()
+            })
+        })
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// The quoted `#include` paths in DM source, `/`-separated. Library
+/// includes (`#include <...>`) are skipped.
+pub fn parse_includes(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim_start().strip_prefix("#include")?.trim();
+            let path = rest.strip_prefix('"')?.split('"').next()?;
+            Some(path.replace('\\', "/"))
+        })
+        .collect()
+}
+
+/// The procs and verbs newly defined in DM `source` from `file`. Overrides
+/// of existing procs (no `proc/` or `verb/` in their path) aren't included.
+///
+/// This follows DM's indentation-based paths but doesn't fully parse the
+/// language; proc bodies are skipped by indentation.
+pub fn parse_procs(file: &str, source: &str) -> Vec<ProcDef> {
+    parse_defs(file, source)
+        .defs
+        .into_iter()
+        .filter_map(|(def, new)| new.then_some(def))
+        .collect()
+}
+
+/// The procs (including overrides) in DM `source` from `file` with a
+/// [`REDACT_ANNOTATION`] comment on the line directly above, and the lines
+/// of any annotations that aren't above a proc definition.
+pub fn parse_annotations(file: &str, source: &str) -> (Vec<ProcDef>, Vec<u32>) {
+    let parsed = parse_defs(file, source);
+    let mut procs = Vec::new();
+    let mut dangling = Vec::new();
+    for line in parsed.annotations {
+        match parsed.defs.iter().find(|(def, _)| def.line == line + 1) {
+            Some((def, _)) => procs.push(def.clone()),
+            None => dangling.push(line),
+        }
+    }
+    (procs, dangling)
+}
+
+/// Proc definitions and annotation comments found by [`parse_defs`].
+struct Parsed {
+    /// Every proc definition, and whether it defines a new proc rather than
+    /// overriding one.
+    defs: Vec<(ProcDef, bool)>,
+    /// 1-based lines holding a [`REDACT_ANNOTATION`].
+    annotations: Vec<u32>,
+}
+
+fn parse_defs(file: &str, source: &str) -> Parsed {
+    let mut parsed = Parsed {
+        defs: Vec::new(),
+        annotations: Vec::new(),
+    };
+    // Path segments contributed by each enclosing indentation level.
+    let mut stack: Vec<String> = Vec::new();
+    // Indentation of the proc whose body is being skipped.
+    let mut body: Option<usize> = None;
+    let mut in_comment = false;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line_number = index as u32 + 1;
+        if !in_comment && is_annotation(raw) {
+            parsed.annotations.push(line_number);
+        }
+        let line = strip_comments(raw, &mut in_comment);
+        let content = line.trim_start();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let indent = indent_level(&line);
+        match body {
+            Some(level) if indent > level => continue,
+            _ => body = None,
+        }
+
+        let head_len = content
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '/'))
+            .unwrap_or(content.len());
+        let (head, rest) = content.split_at(head_len);
+        if head.is_empty() {
+            continue;
+        }
+        stack.resize(indent, String::new());
+
+        let rest = rest.trim_start();
+        if rest.starts_with('(') {
+            let segments: Vec<&str> = stack
+                .iter()
+                .flat_map(|s| s.split('/'))
+                .chain(head.split('/'))
+                .filter(|s| !s.is_empty())
+                .collect();
+            let new =
+                matches!(segments.as_slice(), [.., kind, _] if *kind == "proc" || *kind == "verb");
+            let def = ProcDef {
+                file: file.to_owned(),
+                path: format!("/{}", segments.join("/")),
+                line: line_number,
+            };
+            parsed.defs.push((def, new));
+            body = Some(indent);
+        } else if rest.is_empty() || rest.starts_with('{') {
+            stack.push(head.to_owned());
+        }
+    }
+    parsed
+}
+
+/// Whether `line` is a [`REDACT_ANNOTATION`] comment.
+fn is_annotation(line: &str) -> bool {
+    line.trim_start()
+        .strip_prefix("//")
+        .is_some_and(|comment| comment.trim() == REDACT_ANNOTATION)
+}
+
+/// Indentation depth: one per tab, or per four spaces.
+fn indent_level(line: &str) -> usize {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    for c in line.chars() {
+        match c {
+            '\t' => tabs += 1,
+            ' ' => spaces += 1,
+            _ => break,
+        }
+    }
+    tabs + spaces / 4
+}
+
+/// `line` without `//` and `/* */` comments, tracking block comments that
+/// span lines in `in_comment`. Text inside string literals is left alone.
+fn strip_comments(line: &str, in_comment: &mut bool) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if *in_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_comment = false;
+            }
+            continue;
+        }
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => break,
+            ('/', Some('*')) => {
+                chars.next();
+                *in_comment = true;
+            }
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srcloc::SrcLoc;
+
+    fn paths(defs: &[ProcDef]) -> Vec<(&str, u32)> {
+        defs.iter().map(|d| (d.path.as_str(), d.line)).collect()
+    }
+
+    #[test]
+    fn procs_follow_indentation() {
+        let source = "\
+/datum/secret
+\tvar/x = 1
+\tproc/plan(a, b)
+\t\tif(a)
+\t\t\treturn b
+\tverb/peek()
+\t\tset name = \"Peek\"
+
+/datum/secret/proc/flat()
+/datum/secret/New()
+\t..()
+/obj
+    proc/spaced()
+/mob/Life()
+";
+        let defs = parse_procs("code_secret/a.dm", source);
+        assert_eq!(
+            paths(&defs),
+            [
+                ("/datum/secret/proc/plan", 3),
+                ("/datum/secret/verb/peek", 6),
+                ("/datum/secret/proc/flat", 9),
+                ("/obj/proc/spaced", 13),
+            ]
+        );
+        assert!(defs.iter().all(|d| d.file == "code_secret/a.dm"));
+    }
+
+    #[test]
+    fn codebase_rules_match_files_and_procs() {
+        let codebase = Codebase {
+            dme: PathBuf::from("goon.dme"),
+            submodules: vec!["+secret".into()],
+            secret_files: vec!["+secret/code/a.dm".into()],
+            secret_procs: vec![ProcDef {
+                file: "+secret/code/a.dm".into(),
+                path: "/datum/secret/proc/plan".into(),
+                line: 3,
+            }],
+            missing: Vec::new(),
+        };
+        let rules = codebase.rules();
+        assert_eq!(rules.iter().count(), 2);
+
+        let loc = |function: &str, file: &str| SrcLoc {
+            function: function.into(),
+            file: file.into(),
+            ..SrcLoc::default()
+        };
+        let redacted = |l: &SrcLoc| rules.check(l).action() == Action::Redact;
+        assert!(redacted(&loc("", "+Secret\\code\\A.dm")));
+        assert!(redacted(&loc("/datum/secret/proc/plan", "code/x.dm")));
+        assert!(!redacted(&loc("/datum/Secret/proc/plan", "code/x.dm")));
+        assert!(!redacted(&loc(
+            "/datum/secret/proc/plan2",
+            "+secret/code/b.dm"
+        )));
+    }
+
+    #[test]
+    fn secret_files_fold_case() {
+        let codebase = Codebase {
+            dme: PathBuf::from("goon.dme"),
+            submodules: vec!["+Geheimnisse/Straße".into()],
+            secret_files: Vec::new(),
+            secret_procs: Vec::new(),
+            missing: Vec::new(),
+        };
+        assert!(codebase.is_secret("+geheimnisse/STRASSE/a.dm"));
+        assert!(codebase.is_secret("+GEHEIMNISSE/straße/a.dm"));
+        assert!(!codebase.is_secret("+geheimnisse/strassen/a.dm"));
+        assert!(!codebase.is_secret("+geheimnisse/strasse"));
+    }
+
+    #[test]
+    fn procs_skip_comments() {
+        let source = "\
+/datum/a
+\t// proc/commented()
+\t/* proc/blocked()
+\tproc/still_blocked() */
+\tproc/real() // proc/trailing()
+";
+        assert_eq!(
+            paths(&parse_procs("a.dm", source)),
+            [("/datum/a/proc/real", 5)]
+        );
+    }
+
+    #[test]
+    fn annotations_resolve_to_the_next_proc() {
+        let source = "\
+/datum/antagonist
+\t// utracy:redact
+\tproc/pick_objectives()
+\t\treturn
+\t//utracy:redact
+\tNew()
+\t\t..()
+
+// utracy:redact
+var/global/loose = 1
+/* // utracy:redact */
+/proc/unmarked()
+";
+        let (procs, dangling) = parse_annotations("code/antag.dm", source);
+        assert_eq!(
+            paths(&procs),
+            [
+                ("/datum/antagonist/proc/pick_objectives", 3),
+                ("/datum/antagonist/New", 6),
+            ]
+        );
+        assert_eq!(dangling, [9]);
+
+        let annotations = Annotations {
+            procs,
+            dangling: Vec::new(),
+        };
+        let rules = annotations.rules();
+        let loc = |function: &str, line| SrcLoc {
+            function: function.into(),
+            file: "code\\antag.dm".into(),
+            line,
+            ..SrcLoc::default()
+        };
+        let redacted = |l: &SrcLoc| rules.check(l).action() == Action::Redact;
+        assert!(redacted(&loc("/datum/antagonist/proc/pick_objectives", 0)));
+        assert!(redacted(&loc("/datum/antagonist/proc/New", 0)));
+        assert!(redacted(&loc("", 6)));
+        assert!(!redacted(&loc("/proc/unmarked", 12)));
+    }
+
+    #[test]
+    fn public_tree_leaves_out_submodules() {
+        let root = std::env::temp_dir().join(format!("utracy-public-tree-{}", std::process::id()));
+        for file in [
+            "code/a.dm",
+            "code/b.txt",
+            ".git/c.dm",
+            "+secret/code_secret/d.dm",
+            "modular/private/e.dm",
+            "modular/public/f.dm",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(
+            root.join(".gitmodules"),
+            "[submodule \"+secret\"]\n\tpath = +secret\n\tpath = modular/private\n",
+        )
+        .unwrap();
+
+        let mut all = index_tree(&root).unwrap();
+        let mut public = index_public_tree(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        all.sort();
+        public.sort();
+        assert_eq!(
+            all,
+            [
+                "+secret/code_secret/d.dm",
+                "code/a.dm",
+                "modular/private/e.dm",
+                "modular/public/f.dm",
+            ]
+        );
+        assert_eq!(public, ["code/a.dm", "modular/public/f.dm"]);
+    }
+
+    #[test]
+    fn strip_comments_keeps_strings() {
+        let mut in_comment = false;
+        assert_eq!(
+            strip_comments(r#"x = "a // b \" c" // tail"#, &mut in_comment),
+            r#"x = "a // b \" c" "#
+        );
+        assert_eq!(strip_comments("a /* b", &mut in_comment), "a ");
+        assert!(in_comment);
+        assert_eq!(strip_comments("still */ c", &mut in_comment), " c");
+        assert!(!in_comment);
+    }
+
+    #[test]
+    fn join_path_folds_dots() {
+        assert_eq!(join_path("", "code/a.dm"), "code/a.dm");
+        assert_eq!(join_path("+secret", "./b.dm"), "+secret/b.dm");
+        assert_eq!(join_path("+secret/code", "../c.dm"), "+secret/c.dm");
+        assert_eq!(join_path("a", "../../d.dm"), "d.dm");
+    }
+
+    #[test]
+    fn includes_and_gitmodules() {
+        let dme = "\
+// BEGIN_INCLUDE
+#include \"code\\_globalvars.dm\"
+  #include \"+secret/secret.dme\"
+#include <lib.dm>
+// #include \"commented.dm\"
+";
+        assert_eq!(
+            parse_includes(dme),
+            ["code/_globalvars.dm", "+secret/secret.dme"]
+        );
+
+        let gitmodules = "\
+[submodule \"+secret\"]
+\tpath = +secret
+\turl = git@example.com:secret.git
+[submodule \"modular\"]
+\tpath = modular\\private/
+";
+        assert_eq!(parse_gitmodules(gitmodules), ["+secret", "modular/private"]);
+    }
+}
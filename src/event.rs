@@ -0,0 +1,266 @@
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::{Context, Result, bail};
+
+use crate::io::{read_i64, read_u32};
+use crate::version::Version;
+
+// Event type tags (first byte of every event record). byond-tracy writes
+// Tracy's `QueueType` values (client/TracyQueue.hpp) for the protocol
+// revision it targets, rather than numbering its own records.
+const TAG_ZONE_BEGIN: u8 = 15; // QueueType::ZoneBegin
+const TAG_ZONE_END: u8 = 17; // QueueType::ZoneEnd
+const TAG_ZONE_COLOR: u8 = 62; // QueueType::ZoneColor
+const TAG_FRAME_MARK: u8 = 64; // QueueType::FrameMarkMsg
+
+/// A single record from the event stream that follows the srcloc table.
+///
+/// Every record is a one-byte type tag followed by its little-endian fields.
+/// Timestamps are raw timer ticks; see the header's timer multiplier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A zone was entered on thread `tid`. `srcloc` indexes the srcloc table.
+    ZoneBegin {
+        tid: u32,
+        srcloc: u32,
+        timestamp: i64,
+    },
+    /// The innermost open zone on thread `tid` was left.
+    ZoneEnd { tid: u32, timestamp: i64 },
+    /// Override the color of the innermost open zone on thread `tid`.
+    ZoneColor { tid: u32, color: u32 },
+    /// End of a frame.
+    FrameMark { timestamp: i64 },
+}
+
+impl Event {
+    /// Read the next event encoded as `version` from `r`, or `None` at a
+    /// clean end of stream.
+    pub fn read<R: Read>(r: &mut R, version: Version) -> Result<Option<Self>> {
+        let mut tag = [0u8; 1];
+        loop {
+            match r.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("reading event type"),
+            }
+        }
+
+        let event = match tag[0] {
+            TAG_ZONE_BEGIN => Event::ZoneBegin {
+                tid: read_u32(r).context("reading zone_begin.tid")?,
+                srcloc: read_u32(r).context("reading zone_begin.srcloc")?,
+                timestamp: read_i64(r).context("reading zone_begin.timestamp")?,
+            },
+            TAG_ZONE_END => Event::ZoneEnd {
+                tid: read_u32(r).context("reading zone_end.tid")?,
+                timestamp: read_i64(r).context("reading zone_end.timestamp")?,
+            },
+            TAG_ZONE_COLOR if version.has_zone_color() => Event::ZoneColor {
+                tid: read_u32(r).context("reading zone_color.tid")?,
+                color: read_u32(r).context("reading zone_color.color")?,
+            },
+            TAG_FRAME_MARK => Event::FrameMark {
+                timestamp: read_i64(r).context("reading frame_mark.timestamp")?,
+            },
+            other => bail!("unknown event type {other} for .utracy version {version}"),
+        };
+        Ok(Some(event))
+    }
+
+    /// Write this event to `w`, encoded as `version`.
+    pub fn write<W: Write>(&self, w: &mut W, version: Version) -> Result<()> {
+        let mut buf = [0u8; 17];
+        let len = match *self {
+            Event::ZoneBegin {
+                tid,
+                srcloc,
+                timestamp,
+            } => {
+                buf[0] = TAG_ZONE_BEGIN;
+                buf[1..5].copy_from_slice(&tid.to_le_bytes());
+                buf[5..9].copy_from_slice(&srcloc.to_le_bytes());
+                buf[9..17].copy_from_slice(&timestamp.to_le_bytes());
+                17
+            }
+            Event::ZoneEnd { tid, timestamp } => {
+                buf[0] = TAG_ZONE_END;
+                buf[1..5].copy_from_slice(&tid.to_le_bytes());
+                buf[5..13].copy_from_slice(&timestamp.to_le_bytes());
+                13
+            }
+            Event::ZoneColor { .. } if !version.has_zone_color() => {
+                bail!("zone color events cannot be written as .utracy version {version}")
+            }
+            Event::ZoneColor { tid, color } => {
+                buf[0] = TAG_ZONE_COLOR;
+                buf[1..5].copy_from_slice(&tid.to_le_bytes());
+                buf[5..9].copy_from_slice(&color.to_le_bytes());
+                9
+            }
+            Event::FrameMark { timestamp } => {
+                buf[0] = TAG_FRAME_MARK;
+                buf[1..9].copy_from_slice(&timestamp.to_le_bytes());
+                9
+            }
+        };
+        w.write_all(&buf[..len]).context("writing event")
+    }
+
+    /// The thread this event belongs to, if any.
+    pub fn tid(&self) -> Option<u32> {
+        match *self {
+            Event::ZoneBegin { tid, .. }
+            | Event::ZoneEnd { tid, .. }
+            | Event::ZoneColor { tid, .. } => Some(tid),
+            Event::FrameMark { .. } => None,
+        }
+    }
+}
+
+/// Streaming decoder over an event stream.
+///
+/// Yields events until the end of the stream; after the first error the
+/// iterator is exhausted.
+#[derive(Debug)]
+pub struct EventReader<R> {
+    inner: R,
+    version: Version,
+    index: u64,
+    done: bool,
+}
+
+impl<R: Read> EventReader<R> {
+    /// `inner` must be positioned just after the srcloc table.
+    pub fn new(inner: R, version: Version) -> Self {
+        Self {
+            inner,
+            version,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Number of events decoded so far.
+    pub fn decoded(&self) -> u64 {
+        self.index
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match Event::read(&mut self.inner, self.version) {
+            Ok(Some(event)) => {
+                self.index += 1;
+                Some(Ok(event))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.context(format!("decoding event #{}", self.index))))
+            }
+        }
+    }
+}
+
+/// Streaming encoder for an event stream.
+#[derive(Debug)]
+pub struct EventWriter<W> {
+    inner: W,
+    version: Version,
+    count: u64,
+}
+
+impl<W: Write> EventWriter<W> {
+    /// `inner` must be positioned just after the srcloc table.
+    pub fn new(inner: W, version: Version) -> Self {
+        Self {
+            inner,
+            version,
+            count: 0,
+        }
+    }
+
+    pub fn write(&mut self, event: &Event) -> Result<()> {
+        event
+            .write(&mut self.inner, self.version)
+            .with_context(|| format!("encoding event #{}", self.count))?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of events written so far.
+    pub fn written(&self) -> u64 {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_events() -> Vec<Event> {
+        vec![
+            Event::ZoneBegin {
+                tid: 1,
+                srcloc: 7,
+                timestamp: -3,
+            },
+            Event::ZoneColor {
+                tid: 1,
+                color: 0x00FF_00FF,
+            },
+            Event::ZoneEnd {
+                tid: 1,
+                timestamp: i64::MAX,
+            },
+            Event::FrameMark { timestamp: 42 },
+        ]
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let events = sample_events();
+        let mut writer = EventWriter::new(Vec::new(), Version::V2);
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        assert_eq!(writer.written(), 4);
+        let bytes = writer.into_inner();
+        assert_eq!(bytes.len(), 17 + 9 + 13 + 9);
+
+        let decoded: Vec<Event> = EventReader::new(bytes.as_slice(), Version::V2)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(decoded, events);
+    }
+
+    #[test]
+    fn truncated_event_is_an_error() {
+        let mut bytes = Vec::new();
+        Event::FrameMark { timestamp: 1 }
+            .write(&mut bytes, Version::V2)
+            .unwrap();
+        bytes.pop();
+        let mut reader = EventReader::new(bytes.as_slice(), Version::V2);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
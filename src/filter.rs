@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use crate::event::Event;
+
+/// What happens to the zones of one input srcloc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneAction {
+    /// Keep the zones, pointing them at output srcloc `srcloc`. With
+    /// `color: false` their zone color events are dropped.
+    Keep { srcloc: u32, color: bool },
+    /// Leave the zones out of the output.
+    Drop,
+}
+
+/// State of an open zone on a thread's stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open {
+    Kept { color: bool },
+    Dropped,
+}
+
+/// Rewrites the srcloc index of zone events and drops the zones whose
+/// srcloc was removed, keeping every thread's zone stack balanced.
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    /// Indexed by input srcloc.
+    actions: Vec<ZoneAction>,
+    /// Also drop every zone nested inside a dropped one.
+    subtrees: bool,
+    stacks: HashMap<u32, Vec<Open>>,
+}
+
+impl EventFilter {
+    pub fn new(actions: Vec<ZoneAction>, subtrees: bool) -> Self {
+        Self {
+            actions,
+            subtrees,
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// The rewritten event, or `None` if it should be left out.
+    ///
+    /// `ZoneBegin` events must reference a srcloc inside the action table.
+    pub fn filter(&mut self, event: Event) -> Option<Event> {
+        match event {
+            Event::ZoneBegin {
+                tid,
+                srcloc,
+                timestamp,
+            } => {
+                let stack = self.stacks.entry(tid).or_default();
+                let in_dropped = self.subtrees && stack.last() == Some(&Open::Dropped);
+                match self.actions[srcloc as usize] {
+                    ZoneAction::Keep { srcloc, color } if !in_dropped => {
+                        stack.push(Open::Kept { color });
+                        Some(Event::ZoneBegin {
+                            tid,
+                            srcloc,
+                            timestamp,
+                        })
+                    }
+                    _ => {
+                        stack.push(Open::Dropped);
+                        None
+                    }
+                }
+            }
+            // An end without a matching begin (capture started mid-zone) is
+            // passed through untouched.
+            Event::ZoneEnd { tid, .. } => match self.stacks.get_mut(&tid).and_then(|s| s.pop()) {
+                Some(Open::Dropped) => None,
+                _ => Some(event),
+            },
+            Event::ZoneColor { tid, .. } => match self.stacks.get(&tid).and_then(|s| s.last()) {
+                Some(Open::Dropped | Open::Kept { color: false }) => None,
+                _ => Some(event),
+            },
+            Event::FrameMark { .. } => Some(event),
+        }
+    }
+}
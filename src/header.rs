@@ -0,0 +1,336 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result, bail};
+
+use crate::version::Version;
+
+/// Size of the fixed file header in bytes.
+pub const HEADER_SIZE: usize = 1200;
+/// `"utracydm"` as a little-endian `u64`.
+pub const FILE_SIGNATURE: u64 = 0x6D64796361727475;
+
+// Field offsets. The header is the naturally aligned C struct written by
+// byond-tracy, so there are padding holes after `version` and `flags`.
+const SIG_OFFSET: usize = 0;
+const VER_OFFSET: usize = 8;
+const PAD0_OFFSET: usize = 12;
+const MULTIPLIER_OFFSET: usize = 16;
+const INIT_BEGIN_OFFSET: usize = 24;
+const INIT_END_OFFSET: usize = 32;
+const DELAY_OFFSET: usize = 40;
+const RESOLUTION_OFFSET: usize = 48;
+const EPOCH_OFFSET: usize = 56;
+const EXEC_TIME_OFFSET: usize = 64;
+const PROCESS_ID_OFFSET: usize = 72;
+const SAMPLING_PERIOD_OFFSET: usize = 80;
+const FLAGS_OFFSET: usize = 88;
+const PAD1_OFFSET: usize = 89;
+const CPU_ARCH_OFFSET: usize = 92;
+const CPU_MANUFACTURER_OFFSET: usize = 96;
+const CPU_ID_OFFSET: usize = 108;
+const PROGRAM_NAME_OFFSET: usize = 112;
+const HOST_INFO_OFFSET: usize = 176;
+
+pub const CPU_MANUFACTURER_LEN: usize = CPU_ID_OFFSET - CPU_MANUFACTURER_OFFSET;
+pub const PROGRAM_NAME_LEN: usize = HOST_INFO_OFFSET - PROGRAM_NAME_OFFSET;
+pub const HOST_INFO_LEN: usize = HEADER_SIZE - HOST_INFO_OFFSET;
+
+/// The 1200-byte `.utracy` file header.
+///
+/// Text fields are NUL-padded byte arrays and are kept verbatim, together
+/// with the struct padding, so [`Header::to_bytes`] reproduces the original
+/// header exactly unless a field was edited.
+#[derive(Clone, PartialEq)]
+pub struct Header {
+    pub signature: u64,
+    pub version: u32,
+    /// Timer ticks to nanoseconds.
+    pub multiplier: f64,
+    pub init_begin: i64,
+    pub init_end: i64,
+    pub delay: i64,
+    pub resolution: i64,
+    /// Wall-clock capture start, in seconds since the Unix epoch.
+    pub epoch: i64,
+    pub exec_time: i64,
+    pub process_id: i64,
+    pub sampling_period: i64,
+    pub flags: u8,
+    pub cpu_arch: u32,
+    pub cpu_manufacturer: [u8; CPU_MANUFACTURER_LEN],
+    pub cpu_id: u32,
+    pub program_name: [u8; PROGRAM_NAME_LEN],
+    pub host_info: [u8; HOST_INFO_LEN],
+    pad0: [u8; MULTIPLIER_OFFSET - PAD0_OFFSET],
+    pad1: [u8; CPU_ARCH_OFFSET - PAD1_OFFSET],
+}
+
+impl Header {
+    /// Read and validate a header from `r`.
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let mut bytes = [0u8; HEADER_SIZE];
+        r.read_exact(&mut bytes)
+            .context("reading file header (expected 1200 bytes)")?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decode and validate a header from its raw bytes.
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Result<Self> {
+        let header = Self {
+            signature: u64::from_le_bytes(field(bytes, SIG_OFFSET)),
+            version: u32::from_le_bytes(field(bytes, VER_OFFSET)),
+            multiplier: f64::from_le_bytes(field(bytes, MULTIPLIER_OFFSET)),
+            init_begin: i64::from_le_bytes(field(bytes, INIT_BEGIN_OFFSET)),
+            init_end: i64::from_le_bytes(field(bytes, INIT_END_OFFSET)),
+            delay: i64::from_le_bytes(field(bytes, DELAY_OFFSET)),
+            resolution: i64::from_le_bytes(field(bytes, RESOLUTION_OFFSET)),
+            epoch: i64::from_le_bytes(field(bytes, EPOCH_OFFSET)),
+            exec_time: i64::from_le_bytes(field(bytes, EXEC_TIME_OFFSET)),
+            process_id: i64::from_le_bytes(field(bytes, PROCESS_ID_OFFSET)),
+            sampling_period: i64::from_le_bytes(field(bytes, SAMPLING_PERIOD_OFFSET)),
+            flags: bytes[FLAGS_OFFSET],
+            cpu_arch: u32::from_le_bytes(field(bytes, CPU_ARCH_OFFSET)),
+            cpu_manufacturer: field(bytes, CPU_MANUFACTURER_OFFSET),
+            cpu_id: u32::from_le_bytes(field(bytes, CPU_ID_OFFSET)),
+            program_name: field(bytes, PROGRAM_NAME_OFFSET),
+            host_info: field(bytes, HOST_INFO_OFFSET),
+            pad0: field(bytes, PAD0_OFFSET),
+            pad1: field(bytes, PAD1_OFFSET),
+        };
+
+        // Validate signature (u64 LE at offset 0)
+        let sig = header.signature;
+        if sig != FILE_SIGNATURE {
+            bail!("invalid .utracy signature: got 0x{sig:016X}, expected 0x{FILE_SIGNATURE:016X}");
+        }
+
+        // Validate version (u32 LE at offset 8)
+        header.format_version()?;
+
+        Ok(header)
+    }
+
+    /// Encode the header back into its raw bytes.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        put(&mut bytes, SIG_OFFSET, &self.signature.to_le_bytes());
+        put(&mut bytes, VER_OFFSET, &self.version.to_le_bytes());
+        put(&mut bytes, PAD0_OFFSET, &self.pad0);
+        put(
+            &mut bytes,
+            MULTIPLIER_OFFSET,
+            &self.multiplier.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            INIT_BEGIN_OFFSET,
+            &self.init_begin.to_le_bytes(),
+        );
+        put(&mut bytes, INIT_END_OFFSET, &self.init_end.to_le_bytes());
+        put(&mut bytes, DELAY_OFFSET, &self.delay.to_le_bytes());
+        put(
+            &mut bytes,
+            RESOLUTION_OFFSET,
+            &self.resolution.to_le_bytes(),
+        );
+        put(&mut bytes, EPOCH_OFFSET, &self.epoch.to_le_bytes());
+        put(&mut bytes, EXEC_TIME_OFFSET, &self.exec_time.to_le_bytes());
+        put(
+            &mut bytes,
+            PROCESS_ID_OFFSET,
+            &self.process_id.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            SAMPLING_PERIOD_OFFSET,
+            &self.sampling_period.to_le_bytes(),
+        );
+        bytes[FLAGS_OFFSET] = self.flags;
+        put(&mut bytes, PAD1_OFFSET, &self.pad1);
+        put(&mut bytes, CPU_ARCH_OFFSET, &self.cpu_arch.to_le_bytes());
+        put(&mut bytes, CPU_MANUFACTURER_OFFSET, &self.cpu_manufacturer);
+        put(&mut bytes, CPU_ID_OFFSET, &self.cpu_id.to_le_bytes());
+        put(&mut bytes, PROGRAM_NAME_OFFSET, &self.program_name);
+        put(&mut bytes, HOST_INFO_OFFSET, &self.host_info);
+        bytes
+    }
+
+    /// The format revision named by the `version` field.
+    pub fn format_version(&self) -> Result<Version> {
+        Version::from_u32(self.version)
+    }
+
+    /// Write the header to `w`.
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.to_bytes()).context("writing header")
+    }
+
+    pub fn cpu_manufacturer_str(&self) -> Cow<'_, str> {
+        fixed_str(&self.cpu_manufacturer)
+    }
+
+    pub fn program_name_str(&self) -> Cow<'_, str> {
+        fixed_str(&self.program_name)
+    }
+
+    pub fn host_info_str(&self) -> Cow<'_, str> {
+        fixed_str(&self.host_info)
+    }
+
+    /// Replace the program name, truncating to fit.
+    pub fn set_program_name(&mut self, s: &str) {
+        set_fixed_str(&mut self.program_name, s);
+    }
+
+    /// Replace the host info, truncating to fit.
+    pub fn set_host_info(&mut self, s: &str) {
+        set_fixed_str(&mut self.host_info, s);
+    }
+
+    /// Replace the CPU manufacturer, truncating to fit.
+    pub fn set_cpu_manufacturer(&mut self, s: &str) {
+        set_fixed_str(&mut self.cpu_manufacturer, s);
+    }
+}
+
+impl fmt::Debug for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Header")
+            .field("signature", &format_args!("0x{:016X}", self.signature))
+            .field("version", &self.version)
+            .field("multiplier", &self.multiplier)
+            .field("init_begin", &self.init_begin)
+            .field("init_end", &self.init_end)
+            .field("delay", &self.delay)
+            .field("resolution", &self.resolution)
+            .field("epoch", &self.epoch)
+            .field("exec_time", &self.exec_time)
+            .field("process_id", &self.process_id)
+            .field("sampling_period", &self.sampling_period)
+            .field("flags", &self.flags)
+            .field("cpu_arch", &self.cpu_arch)
+            .field("cpu_manufacturer", &self.cpu_manufacturer_str())
+            .field("cpu_id", &self.cpu_id)
+            .field("program_name", &self.program_name_str())
+            .field("host_info", &self.host_info_str())
+            .finish()
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "signature:        0x{:016X}", self.signature)?;
+        writeln!(f, "version:          {}", self.version)?;
+        writeln!(f, "timer multiplier: {}", self.multiplier)?;
+        writeln!(f, "init begin:       {}", self.init_begin)?;
+        writeln!(f, "init end:         {}", self.init_end)?;
+        writeln!(f, "delay:            {}", self.delay)?;
+        writeln!(f, "resolution:       {}", self.resolution)?;
+        writeln!(f, "epoch:            {}", self.epoch)?;
+        writeln!(f, "exec time:        {}", self.exec_time)?;
+        writeln!(f, "process id:       {}", self.process_id)?;
+        writeln!(f, "sampling period:  {}", self.sampling_period)?;
+        writeln!(f, "flags:            0x{:02X}", self.flags)?;
+        writeln!(f, "cpu arch:         0x{:08X}", self.cpu_arch)?;
+        writeln!(f, "cpu manufacturer: {:?}", self.cpu_manufacturer_str())?;
+        writeln!(f, "cpu id:           0x{:08X}", self.cpu_id)?;
+        writeln!(f, "program name:     {:?}", self.program_name_str())?;
+        write!(f, "host info:        {:?}", self.host_info_str())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Byte layout helpers
+// ---------------------------------------------------------------------------
+
+fn field<const N: usize>(bytes: &[u8; HEADER_SIZE], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+fn put(bytes: &mut [u8; HEADER_SIZE], offset: usize, src: &[u8]) {
+    bytes[offset..offset + src.len()].copy_from_slice(src);
+}
+
+/// Text of a NUL-padded fixed-size field, up to the first NUL.
+fn fixed_str(buf: &[u8]) -> Cow<'_, str> {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end])
+}
+
+/// Overwrite a NUL-padded fixed-size field with `s`, truncated on a char
+/// boundary so that at least one terminating NUL remains.
+fn set_fixed_str(buf: &mut [u8], s: &str) {
+    let mut len = s.len().min(buf.len() - 1);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.fill(0);
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valid header with every byte, padding included, set to something
+    /// other than zero.
+    fn sample_bytes() -> [u8; HEADER_SIZE] {
+        let mut bytes: [u8; HEADER_SIZE] = std::array::from_fn(|i| (i % 251) as u8 + 1);
+        put(&mut bytes, SIG_OFFSET, &FILE_SIGNATURE.to_le_bytes());
+        put(&mut bytes, VER_OFFSET, &2u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let bytes = sample_bytes();
+        let header = Header::from_bytes(&bytes).unwrap();
+        assert_eq!(header.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn edits_touch_only_their_field() {
+        let bytes = sample_bytes();
+        let mut header = Header::from_bytes(&bytes).unwrap();
+        header.epoch = 0;
+        header.set_program_name("dreamdaemon");
+        let edited = header.to_bytes();
+
+        for (i, (a, b)) in bytes.iter().zip(&edited).enumerate() {
+            let in_epoch = (EPOCH_OFFSET..EPOCH_OFFSET + 8).contains(&i);
+            let in_name = (PROGRAM_NAME_OFFSET..HOST_INFO_OFFSET).contains(&i);
+            if !in_epoch && !in_name {
+                assert_eq!(a, b, "byte {i} changed");
+            }
+        }
+        let header = Header::from_bytes(&edited).unwrap();
+        assert_eq!(header.epoch, 0);
+        assert_eq!(header.program_name_str(), "dreamdaemon");
+    }
+
+    #[test]
+    fn rejects_bad_signature_and_version() {
+        let mut bytes = sample_bytes();
+        bytes[0] ^= 0xFF;
+        assert!(Header::from_bytes(&bytes).is_err());
+
+        let mut bytes = sample_bytes();
+        for version in [1u32, 3, 99] {
+            put(&mut bytes, VER_OFFSET, &version.to_le_bytes());
+            assert!(Header::from_bytes(&bytes).is_err(), "version {version}");
+        }
+    }
+
+    #[test]
+    fn fixed_str_truncates_on_char_boundary() {
+        let mut buf = [0xFFu8; 4];
+        set_fixed_str(&mut buf, "aéb");
+        // "aé" is 3 bytes and fits, leaving the terminating NUL.
+        assert_eq!(buf, [b'a', 0xC3, 0xA9, 0]);
+        set_fixed_str(&mut buf, "abé");
+        assert_eq!(buf, [b'a', b'b', 0, 0]);
+        assert_eq!(fixed_str(&buf), "ab");
+    }
+}
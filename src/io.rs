@@ -0,0 +1,112 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+// ---------------------------------------------------------------------------
+// Length-prefixed string helpers (u32 LE length + raw UTF-8 bytes)
+// ---------------------------------------------------------------------------
+
+/// Read a `u32` LE length followed by that many UTF-8 bytes.
+pub fn read_lenpfx_string<R: Read>(r: &mut R) -> Result<String> {
+    let mut len_buf = [0u8; 4];
+    r.read_exact(&mut len_buf)
+        .context("reading string length")?;
+    let len = u32::from_le_bytes(len_buf) as usize;
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes).context("reading string bytes")?;
+    String::from_utf8(bytes).context("string is not valid UTF-8")
+}
+
+/// Write `s` as a `u32` LE length followed by its UTF-8 bytes.
+pub fn write_lenpfx_string<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    w.write_all(&(s.len() as u32).to_le_bytes())
+        .context("writing string length")?;
+    w.write_all(s.as_bytes()).context("writing string bytes")
+}
+
+// ---------------------------------------------------------------------------
+// Fixed-width little-endian integer helpers
+// ---------------------------------------------------------------------------
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_i64<R: Read>(r: &mut R) -> std::io::Result<i64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+// ---------------------------------------------------------------------------
+// SHA-256 over everything passing through a reader/writer
+// ---------------------------------------------------------------------------
+
+/// Reader that hashes everything read through it, if enabled.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R, enabled: bool) -> Self {
+        Self {
+            inner,
+            hasher: enabled.then(Sha256::new),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Option<[u8; 32]> {
+        self.hasher.map(|h| h.finalize().into())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(h) = &mut self.hasher {
+            h.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Writer that hashes everything written through it.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,39 @@
+//! Reader/writer for the `.utracy` profiler capture format produced by
+//! [byond-tracy](https://github.com/ParadiseSS13/byond-tracy) and consumed by
+//! [rtracy](https://github.com/Dimach/rtracy).
+//!
+//! A `.utracy` file is laid out as:
+//!
+//! 1. a fixed 1200-byte [`Header`]
+//! 2. a `u32` LE srcloc count followed by that many [`SrcLoc`] entries
+//! 3. the [`Event`] stream, running to end of file
+//!
+//! The srcloc and event encodings depend on the header's format [`Version`];
+//! readers and writers take the version to use explicitly.
+//!
+//! The [`redact`] module builds on top of this to rewrite secret srclocs, and
+//! [`unredact`] reverses it using the [`sidecar::Sidecar`] it can record.
+
+mod io;
+
+pub mod config;
+pub mod dm;
+pub mod event;
+pub mod filter;
+pub mod header;
+pub mod markers;
+pub mod presets;
+pub mod pseudonym;
+pub mod redact;
+pub mod rules;
+pub mod scrub;
+pub mod sidecar;
+pub mod srcloc;
+pub mod unredact;
+pub mod version;
+
+pub use event::Event;
+pub use header::Header;
+pub use io::{read_lenpfx_string, write_lenpfx_string};
+pub use srcloc::SrcLoc;
+pub use version::Version;
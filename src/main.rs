@@ -1,29 +1,53 @@
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, bail};
-use clap::Parser;
-
-// .utracy file constants
-const HEADER_SIZE: usize = 1200;
-const FILE_SIGNATURE: u64 = 0x6D64796361727475;
-const FILE_VERSION: u32 = 2;
-const SIG_OFFSET: usize = 0;
-const VER_OFFSET: usize = 8;
+use clap::{Args, Parser, Subcommand};
+use utracy::SrcLoc;
+use utracy::config::{Config, Settings};
+use utracy::dm::{Annotations, Codebase, REDACT_ANNOTATION, index_public_tree};
+use utracy::markers::{Field, Marker, Pattern};
+use utracy::presets::{PRESETS, Preset};
+use utracy::pseudonym::Pseudonymizer;
+use utracy::redact::{self, Mode, Options, Report, ZonePolicy};
+use utracy::rules::{Action, Condition, Rule, Rules};
+use utracy::scrub::{FieldPolicy, HeaderScrub, SrcLocScrub};
+use utracy::sidecar::{Sidecar, SidecarKey};
+use utracy::unredact;
 
 const BUF_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
 
-const REDACTED: &str = "<redacted>";
+/// Public path prefixes for --default-deny: the codebase's code, and the
+/// file BYOND's built-in procs are attributed to.
+const DEFAULT_PUBLIC_ROOTS: &[&str] = &["code/", "_std/", "stddef.dm"];
+
+// Markers used when no markers or rules are given (Goonstation layout)
+const DEFAULT_FILE_MARKERS: &[&str] = &["code_secret"];
+const DEFAULT_FN_MARKERS: &[&str] = &["secret"];
+
+/// Environment variable holding the sidecar passphrase.
+const SIDECAR_PASSPHRASE_ENV: &str = "UTRACY_SIDECAR_PASSPHRASE";
 
 /// Rewrite the srcloc table of a .utracy file, replacing name/function/file
 /// fields with <redacted> for any srcloc whose source file path contains
 /// "+secret".
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the input .utracy file
-    input: PathBuf,
+    #[arg(required = true)]
+    input: Option<PathBuf>,
 
     /// Output path (default: <stem>.redacted.utracy in the same dir)
     #[arg(short, long, value_name = "PATH")]
@@ -37,40 +61,485 @@ struct Cli {
     #[arg(long)]
     dry_run: bool,
 
-    /// Substrings matched against the srcloc file path (case-insensitive, repeatable)
-    #[arg(long = "file-marker", value_name = "SUBSTR", default_values = ["code_secret"])]
+    /// Print the decoded file header
+    #[arg(long)]
+    show_header: bool,
+
+    /// Don't look for .utracy-redact.toml config files
+    #[arg(long)]
+    no_config: bool,
+
+    /// TOML file of ordered rules deciding what to keep, redact,
+    /// pseudonymize or drop (repeatable). Checked after the --keep-*
+    /// exceptions and before the markers below
+    #[arg(long = "rules", value_name = "PATH")]
+    rules: Vec<PathBuf>,
+
+    /// Built-in rule set for a known codebase, checked after every other
+    /// rule and marker (repeatable). See the `presets` command
+    #[arg(long = "preset", value_name = "NAME")]
+    presets: Vec<String>,
+
+    /// Redact the files a DM codebase includes from its git submodules, and
+    /// the procs defined in them. PATH is the .dme, or the directory holding
+    /// it
+    #[arg(long, value_name = "PATH")]
+    codebase: Option<PathBuf>,
+
+    /// Redact the procs marked with a `// utracy:redact` comment directly
+    /// above their definition in the .dm files under PATH
+    #[arg(long, value_name = "PATH")]
+    annotations: Option<PathBuf>,
+
+    /// Substrings matched against the srcloc file path (case-insensitive,
+    /// repeatable) [default: code_secret]
+    #[arg(long = "file-marker", value_name = "SUBSTR")]
     file_markers: Vec<String>,
 
-    /// Substrings matched against the srcloc function name (case-insensitive, repeatable)
-    #[arg(long = "fn-marker", value_name = "SUBSTR", default_values = ["secret"])]
+    /// Substrings matched against the srcloc function name (case-insensitive,
+    /// repeatable) [default: secret]
+    #[arg(long = "fn-marker", value_name = "SUBSTR")]
     fn_markers: Vec<String>,
+
+    /// Don't add the default --file-marker and --fn-marker
+    #[arg(long)]
+    no_default_markers: bool,
+
+    /// Substrings matched against the srcloc zone name, which can carry
+    /// dynamic text such as datum or verb names (case-insensitive, repeatable)
+    #[arg(long = "name-marker", value_name = "SUBSTR")]
+    name_markers: Vec<String>,
+
+    /// Glob matched against the whole srcloc file path, e.g. `+secret/**` or
+    /// `code/**/admin_*.dm` (case-insensitive, repeatable)
+    #[arg(long = "file-glob", value_name = "GLOB")]
+    file_globs: Vec<String>,
+
+    /// Regex matched against the srcloc file path (case-sensitive, repeatable)
+    #[arg(long = "file-regex", value_name = "REGEX")]
+    file_regexes: Vec<String>,
+
+    /// Regex matched against the srcloc function name (case-sensitive, repeatable)
+    #[arg(long = "fn-regex", value_name = "REGEX")]
+    fn_regexes: Vec<String>,
+
+    /// Regex matched against the srcloc zone name (case-sensitive, repeatable)
+    #[arg(long = "name-regex", value_name = "REGEX")]
+    name_regexes: Vec<String>,
+
+    /// Redact the srclocs of procs defined within a line range of a file,
+    /// e.g. `code/modules/admin/foo.dm:120-340` (repeatable)
+    #[arg(long = "line-range", value_name = "FILE:START-END")]
+    line_ranges: Vec<String>,
+
+    /// Redact every srcloc whose file isn't under a public root, including
+    /// ones with no file, unless an earlier rule or exception decides it
+    #[arg(long, overrides_with = "no_default_deny")]
+    default_deny: bool,
+
+    /// Turn --default-deny off, including when a config file sets it or
+    /// public roots
+    #[arg(long, conflicts_with_all = ["public_roots", "public_tree"])]
+    no_default_deny: bool,
+
+    /// Path prefix kept by --default-deny (repeatable, implies
+    /// --default-deny) [default: code/, _std/, stddef.dm]
+    #[arg(long = "public-root", value_name = "PREFIX")]
+    public_roots: Vec<String>,
+
+    /// Checkout of the public repository: redact every srcloc whose file
+    /// isn't one of its .dm files (implies --default-deny; the default
+    /// public roots then don't apply)
+    #[arg(long, value_name = "PATH")]
+    public_tree: Option<PathBuf>,
+
+    /// Never redact srclocs whose file path matches this, even if a marker
+    /// does: a substring, glob:<GLOB> or re:<REGEX> (repeatable)
+    #[arg(long = "keep-file", value_name = "PATTERN")]
+    keep_files: Vec<String>,
+
+    /// Never redact srclocs whose function name matches this, even if a
+    /// marker does: a substring, glob:<GLOB> or re:<REGEX> (repeatable)
+    #[arg(long = "keep-fn", value_name = "PATTERN")]
+    keep_fns: Vec<String>,
+
+    /// Never redact srclocs whose zone name matches this, even if a marker
+    /// does: a substring, glob:<GLOB> or re:<REGEX> (repeatable)
+    #[arg(long = "keep-name", value_name = "PATTERN")]
+    keep_names: Vec<String>,
+
+    /// How srclocs matched by a marker or redact rule are written:
+    /// placeholder (replace their text with <redacted>), drop (remove them
+    /// and their zones entirely) or merge (collapse them into one shared
+    /// <redacted> entry) [default: placeholder]
+    #[arg(long, value_name = "MODE")]
+    mode: Option<Mode>,
+
+    /// Key file for pseudonymize rules and --pseudonymize: redacted text
+    /// becomes a stable <redacted:xxxxxxxxxxxx> token keyed on its
+    /// contents, so the same proc can be recognised across captures
+    #[arg(long, value_name = "KEY_FILE")]
+    pseudonym_key: Option<PathBuf>,
+
+    /// Pseudonymize every placeholder redaction, not just those of
+    /// pseudonymize rules (needs --pseudonym-key)
+    #[arg(long, overrides_with = "no_pseudonymize")]
+    pseudonymize: bool,
+
+    /// Turn --pseudonymize off when a config file sets it
+    #[arg(long)]
+    no_pseudonymize: bool,
+
+    /// Write the original details of every redacted srcloc to this
+    /// encrypted sidecar file
+    #[arg(long, value_name = "PATH")]
+    sidecar: Option<PathBuf>,
+
+    /// Encrypt the sidecar to this age public key (repeatable). Without
+    /// one, the passphrase in $UTRACY_SIDECAR_PASSPHRASE is used
+    #[arg(
+        long = "sidecar-recipient",
+        value_name = "AGE_PUBKEY",
+        requires = "sidecar"
+    )]
+    sidecar_recipients: Vec<age::x25519::Recipient>,
+
+    /// Drop the zone begin/end events of redacted srclocs from the event
+    /// stream (implied by --mode drop)
+    #[arg(long, overrides_with = "no_drop_zones")]
+    drop_zones: bool,
+
+    /// Turn --drop-zones and --drop-descendants off when a config file sets
+    /// them
+    #[arg(long, conflicts_with = "drop_descendants")]
+    no_drop_zones: bool,
+
+    /// Also drop every zone nested inside a dropped zone (implies --drop-zones)
+    #[arg(long)]
+    drop_descendants: bool,
+
+    /// Line number of redacted srclocs: keep, blank (zero) or replace:<N>
+    /// [default: blank]
+    #[arg(long, value_name = "POLICY")]
+    redacted_line: Option<FieldPolicy<u32>>,
+
+    /// Zone color of redacted srclocs: keep, blank (zero) or replace:<N>
+    /// [default: blank]
+    #[arg(long, value_name = "POLICY")]
+    redacted_color: Option<FieldPolicy<u32>>,
+
+    /// Blank every identifying header field (host info, program name,
+    /// process id, epoch, exec time) unless overridden below
+    #[arg(long, overrides_with = "no_scrub_header")]
+    scrub_header: bool,
+
+    /// Turn --scrub-header off when a config file sets it
+    #[arg(long)]
+    no_scrub_header: bool,
+
+    /// Header host info: keep, blank or replace:<TEXT>
+    #[arg(long, value_name = "POLICY")]
+    host_info: Option<FieldPolicy<String>>,
+
+    /// Header program name: keep, blank or replace:<TEXT>
+    #[arg(long, value_name = "POLICY")]
+    program_name: Option<FieldPolicy<String>>,
+
+    /// Header process id: keep, blank or replace:<N>
+    #[arg(long, value_name = "POLICY")]
+    process_id: Option<FieldPolicy<i64>>,
+
+    /// Header capture start time: keep, blank or replace:<N>
+    #[arg(long, value_name = "POLICY")]
+    epoch: Option<FieldPolicy<i64>>,
+
+    /// Header program start time: keep, blank or replace:<N>
+    #[arg(long, value_name = "POLICY")]
+    exec_time: Option<FieldPolicy<i64>>,
+
+    /// `[[rule]]` tables from the applied config files, nearest first
+    #[arg(skip)]
+    config_rules: Rules,
+
+    /// The applied config files, nearest first
+    #[arg(skip)]
+    configs: Vec<PathBuf>,
 }
 
-// ---------------------------------------------------------------------------
-// Length-prefixed string helpers (u32 LE length + raw UTF-8 bytes)
-// ---------------------------------------------------------------------------
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Restore a redacted .utracy file to the original using its sidecar
+    Unredact(UnredactArgs),
+    /// List the built-in presets, or print the rules of one
+    Presets(PresetsArgs),
+}
+
+#[derive(Args, Debug)]
+struct PresetsArgs {
+    /// Preset whose rules to print
+    name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct UnredactArgs {
+    /// Path to the redacted .utracy file
+    input: PathBuf,
+
+    /// Sidecar written alongside the redacted file with --sidecar
+    #[arg(long, value_name = "PATH")]
+    sidecar: PathBuf,
+
+    /// age identity file to decrypt the sidecar with. Without one, the
+    /// passphrase in $UTRACY_SIDECAR_PASSPHRASE is used
+    #[arg(long, value_name = "PATH")]
+    identity: Option<PathBuf>,
 
-fn read_lenpfx_string<R: Read>(r: &mut R) -> Result<String> {
-    let mut len_buf = [0u8; 4];
-    r.read_exact(&mut len_buf)
-        .context("reading string length")?;
-    let len = u32::from_le_bytes(len_buf) as usize;
-    let mut bytes = vec![0u8; len];
-    r.read_exact(&mut bytes).context("reading string bytes")?;
-    String::from_utf8(bytes).context("string is not valid UTF-8")
+    /// Output path (default: <stem>.unredacted.utracy in the same dir)
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
 }
 
-fn write_lenpfx_string<W: Write>(w: &mut W, s: &str) -> Result<()> {
-    w.write_all(&(s.len() as u32).to_le_bytes())
-        .context("writing string length")?;
-    w.write_all(s.as_bytes()).context("writing string bytes")
+impl Cli {
+    /// Use the default markers for each of --file-marker and --fn-marker not
+    /// given on the command line. Done before configs are applied, so their
+    /// markers add to the defaults instead of replacing them.
+    fn add_default_markers(&mut self) {
+        if self.no_default_markers {
+            return;
+        }
+        for (markers, defaults) in [
+            (&mut self.file_markers, DEFAULT_FILE_MARKERS),
+            (&mut self.fn_markers, DEFAULT_FN_MARKERS),
+        ] {
+            if markers.is_empty() {
+                markers.extend(defaults.iter().map(|m| m.to_string()));
+            }
+        }
+    }
+
+    /// Layer the discovered config files under the command-line options.
+    fn apply_configs(&mut self, configs: Vec<Config>) {
+        let mut settings = Settings::default();
+        for config in configs {
+            settings.layer(config.settings);
+            self.config_rules.extend(config.rules);
+            self.configs.push(config.path);
+        }
+
+        self.rules.extend(settings.rules_files);
+        self.presets.extend(settings.preset);
+        self.codebase = self.codebase.take().or(settings.codebase);
+        self.annotations = self.annotations.take().or(settings.annotations);
+        self.file_markers.extend(settings.file_marker);
+        self.fn_markers.extend(settings.fn_marker);
+        self.name_markers.extend(settings.name_marker);
+        self.file_globs.extend(settings.file_glob);
+        self.file_regexes.extend(settings.file_regex);
+        self.fn_regexes.extend(settings.fn_regex);
+        self.name_regexes.extend(settings.name_regex);
+        self.line_ranges.extend(settings.line_range);
+        if !self.no_default_deny {
+            self.default_deny |= settings.default_deny.unwrap_or(false);
+        }
+        self.public_roots.extend(settings.public_root);
+        self.public_tree = self.public_tree.take().or(settings.public_tree);
+        self.keep_files.extend(settings.keep_file);
+        self.keep_fns.extend(settings.keep_fn);
+        self.keep_names.extend(settings.keep_name);
+        if self.sidecar_recipients.is_empty() {
+            self.sidecar_recipients = settings.sidecar_recipient;
+        }
+        self.mode = self.mode.or(settings.mode);
+        self.pseudonym_key = self.pseudonym_key.take().or(settings.pseudonym_key);
+        if !self.no_pseudonymize {
+            self.pseudonymize |= settings.pseudonymize.unwrap_or(false);
+        }
+        if !self.no_drop_zones {
+            self.drop_zones |= settings.drop_zones.unwrap_or(false);
+            self.drop_descendants |= settings.drop_descendants.unwrap_or(false);
+        }
+        self.redacted_line = self.redacted_line.take().or(settings.redacted_line);
+        self.redacted_color = self.redacted_color.take().or(settings.redacted_color);
+        if !self.no_scrub_header {
+            self.scrub_header |= settings.scrub_header.unwrap_or(false);
+        }
+        self.host_info = self.host_info.take().or(settings.host_info);
+        self.program_name = self.program_name.take().or(settings.program_name);
+        self.process_id = self.process_id.take().or(settings.process_id);
+        self.epoch = self.epoch.take().or(settings.epoch);
+        self.exec_time = self.exec_time.take().or(settings.exec_time);
+    }
+
+    fn rules(&self) -> Result<Rules> {
+        let mut rules = Rules::new();
+        for (field, patterns) in [
+            (Field::File, &self.keep_files),
+            (Field::Function, &self.keep_fns),
+            (Field::Name, &self.keep_names),
+        ] {
+            for p in patterns {
+                rules.push(Rule::marker(field, Pattern::parse(p)?, Action::Keep));
+            }
+        }
+        for path in &self.rules {
+            rules.extend(Rules::load(path)?);
+        }
+        rules.extend(self.config_rules.clone());
+
+        let mut markers = Vec::new();
+        for m in &self.file_markers {
+            markers.push((Field::File, Pattern::substring(m)));
+        }
+        for m in &self.fn_markers {
+            markers.push((Field::Function, Pattern::substring(m)));
+        }
+        for m in &self.name_markers {
+            markers.push((Field::Name, Pattern::substring(m)));
+        }
+        for glob in &self.file_globs {
+            markers.push((Field::File, Pattern::glob(glob)?));
+        }
+        for re in &self.file_regexes {
+            markers.push((Field::File, Pattern::regex(re)?));
+        }
+        for re in &self.fn_regexes {
+            markers.push((Field::Function, Pattern::regex(re)?));
+        }
+        for re in &self.name_regexes {
+            markers.push((Field::Name, Pattern::regex(re)?));
+        }
+        for (field, pattern) in markers {
+            rules.push(Rule::marker(field, pattern, Action::Redact));
+        }
+        for spec in &self.line_ranges {
+            rules.push(Rule::line_range(spec, Action::Redact)?);
+        }
+        if let Some(path) = &self.codebase {
+            let codebase = Codebase::scan(path)?;
+            if codebase.submodules.is_empty() {
+                bail!(
+                    "no git submodules listed next to {}, so nothing in it is secret",
+                    codebase.dme.display()
+                );
+            }
+            if codebase.secret_files.is_empty() {
+                bail!(
+                    "{} includes no files from its submodules ({})",
+                    codebase.dme.display(),
+                    codebase.submodules.join(", ")
+                );
+            }
+            if !codebase.missing.is_empty() {
+                eprintln!(
+                    "warning: {} secret files aren't in the checkout at {}; only their paths are matched",
+                    codebase.missing.len(),
+                    path.display()
+                );
+            }
+            rules.extend(codebase.rules());
+        }
+        if let Some(path) = &self.annotations {
+            let annotations = Annotations::scan(path)?;
+            if annotations.procs.is_empty() {
+                eprintln!(
+                    "warning: no procs under {} are marked with `// {REDACT_ANNOTATION}`",
+                    path.display()
+                );
+            }
+            for (file, line) in &annotations.dangling {
+                eprintln!(
+                    "warning: {file}:{line}: `// {REDACT_ANNOTATION}` isn't directly above a proc definition"
+                );
+            }
+            rules.extend(annotations.rules());
+        }
+        for name in &self.presets {
+            rules.extend(Preset::find(name)?.rules()?);
+        }
+        if self.default_deny() {
+            // Srclocs are public if under a root, or in the public tree.
+            let mut public = Vec::new();
+            let mut described = Vec::new();
+            let roots = if self.public_roots.is_empty() && self.public_tree.is_none() {
+                DEFAULT_PUBLIC_ROOTS.iter().map(|r| r.to_string()).collect()
+            } else {
+                self.public_roots.clone()
+            };
+            if !roots.is_empty() {
+                public.push(Condition::file_under(&roots)?);
+                described.push(format!("under {}", roots.join(", ")));
+            }
+            if let Some(tree) = &self.public_tree {
+                let files = index_public_tree(tree)?;
+                if files.is_empty() {
+                    bail!("no .dm files found in public tree {}", tree.display());
+                }
+                described.push(format!(
+                    "one of {} files in {}",
+                    files.len(),
+                    tree.display()
+                ));
+                public.push(Condition::Field(Marker::new(
+                    Field::File,
+                    Pattern::one_of(files),
+                )));
+            }
+            rules.push(Rule::deny_unless(
+                Condition::Any(public),
+                Action::Redact,
+                format!("default deny (not {})", described.join(" or ")),
+            ));
+        }
+        Ok(rules)
+    }
+
+    fn default_deny(&self) -> bool {
+        !self.no_default_deny
+            && (self.default_deny || !self.public_roots.is_empty() || self.public_tree.is_some())
+    }
+
+    fn header_scrub(&self) -> HeaderScrub {
+        let mut scrub = if self.scrub_header {
+            HeaderScrub::blank_all()
+        } else {
+            HeaderScrub::default()
+        };
+        if let Some(p) = &self.host_info {
+            scrub.host_info = p.clone();
+        }
+        if let Some(p) = &self.program_name {
+            scrub.program_name = p.clone();
+        }
+        if let Some(p) = &self.process_id {
+            scrub.process_id = p.clone();
+        }
+        if let Some(p) = &self.epoch {
+            scrub.epoch = p.clone();
+        }
+        if let Some(p) = &self.exec_time {
+            scrub.exec_time = p.clone();
+        }
+        scrub
+    }
+
+    fn sidecar_key(&self) -> Result<SidecarKey> {
+        if !self.sidecar_recipients.is_empty() {
+            return Ok(SidecarKey::Recipients(self.sidecar_recipients.clone()));
+        }
+        match std::env::var(SIDECAR_PASSPHRASE_ENV) {
+            Ok(pass) if !pass.is_empty() => Ok(SidecarKey::Passphrase(pass.into())),
+            _ => bail!(
+                "--sidecar needs --sidecar-recipient or a passphrase in ${SIDECAR_PASSPHRASE_ENV}"
+            ),
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Output path resolution
 // ---------------------------------------------------------------------------
 
-fn resolve_output(cli: &Cli) -> Result<Option<PathBuf>> {
+fn resolve_output(cli: &Cli, input: &Path) -> Result<Option<PathBuf>> {
     if cli.dry_run {
         return Ok(None);
     }
@@ -79,7 +548,7 @@ fn resolve_output(cli: &Cli) -> Result<Option<PathBuf>> {
         return Ok(None); // we'll use a temp file; handled separately
     }
 
-    let canonical_in = fs::canonicalize(&cli.input).unwrap_or_else(|_| cli.input.clone());
+    let canonical_in = fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
 
     if let Some(p) = &cli.output {
         let canonical_out = fs::canonicalize(p).unwrap_or_else(|_| p.clone());
@@ -108,133 +577,42 @@ fn resolve_output(cli: &Cli) -> Result<Option<PathBuf>> {
 }
 
 // ---------------------------------------------------------------------------
-// Core redaction logic
+// main
 // ---------------------------------------------------------------------------
 
-fn process<R: Read, W: Write>(
-    reader: &mut R,
-    writer: &mut W,
-    dry_run: bool,
-    file_markers: &[String],
-    fn_markers: &[String],
-) -> Result<Vec<String>> {
-    // -- Header (1200 bytes - calculated) -----------------------------------
-    let mut header = [0u8; HEADER_SIZE];
-    reader
-        .read_exact(&mut header)
-        .context("reading file header (expected 1200 bytes)")?;
-
-    // Validate signature (u64 LE at offset 0)
-    let sig = u64::from_le_bytes(header[SIG_OFFSET..SIG_OFFSET + 8].try_into().unwrap());
-    if sig != FILE_SIGNATURE {
-        bail!("invalid .utracy signature: got 0x{sig:016X}, expected 0x{FILE_SIGNATURE:016X}");
-    }
-
-    // Validate version (u32 LE at offset 8)
-    let ver = u32::from_le_bytes(header[VER_OFFSET..VER_OFFSET + 4].try_into().unwrap());
-    if ver != FILE_VERSION {
-        bail!("unsupported .utracy version: got {ver}, expected {FILE_VERSION}");
-    }
-
-    if !dry_run {
-        writer.write_all(&header).context("writing header")?;
-    }
-
-    // -- srcloc_count (u32 LE) -----------------------------------------------
-    let mut count_buf = [0u8; 4];
-    reader
-        .read_exact(&mut count_buf)
-        .context("reading srcloc_count")?;
-    let srcloc_count = u32::from_le_bytes(count_buf);
-
-    if !dry_run {
-        writer
-            .write_all(&count_buf)
-            .context("writing srcloc_count")?;
-    }
-
-    // -- Srcloc table --------------------------------------------------------
-    let file_markers_lower: Vec<String> = file_markers
-        .iter()
-        .map(|m| m.to_ascii_lowercase())
-        .collect();
-    let fn_markers_lower: Vec<String> = fn_markers.iter().map(|m| m.to_ascii_lowercase()).collect();
-    let mut redacted_fns = Vec::new();
-
-    for _ in 0..srcloc_count {
-        let name = read_lenpfx_string(reader).context("reading srcloc.name")?;
-        let function = read_lenpfx_string(reader).context("reading srcloc.function")?;
-        let file = read_lenpfx_string(reader).context("reading srcloc.file")?;
-
-        let mut line_buf = [0u8; 4];
-        reader
-            .read_exact(&mut line_buf)
-            .context("reading srcloc.line")?;
-        let mut color_buf = [0u8; 4];
-        reader
-            .read_exact(&mut color_buf)
-            .context("reading srcloc.color")?;
-
-        let file_lower = file.to_ascii_lowercase();
-        let fn_lower = function.to_ascii_lowercase();
-        let secret = file_markers_lower
-            .iter()
-            .any(|m| file_lower.contains(m.as_str()))
-            || fn_markers_lower
-                .iter()
-                .any(|m| fn_lower.contains(m.as_str()));
-
-        if secret {
-            redacted_fns.push(function.clone());
-        }
-
-        if !dry_run {
-            let (out_name, out_fn, out_file): (&str, &str, &str) = if secret {
-                (REDACTED, REDACTED, REDACTED)
-            } else {
-                (&name, &function, &file)
-            };
-            write_lenpfx_string(writer, out_name).context("writing srcloc.name")?;
-            write_lenpfx_string(writer, out_fn).context("writing srcloc.function")?;
-            write_lenpfx_string(writer, out_file).context("writing srcloc.file")?;
-            writer.write_all(&line_buf).context("writing srcloc.line")?;
-            writer
-                .write_all(&color_buf)
-                .context("writing srcloc.color")?;
+fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+
+    match &cli.command {
+        Some(Command::Unredact(args)) => run_unredact(args),
+        Some(Command::Presets(args)) => run_presets(args),
+        None => {
+            let input = cli.input.clone().expect("clap requires <INPUT>");
+            cli.add_default_markers();
+            if !cli.no_config {
+                let configs = Config::discover(&input)?;
+                cli.apply_configs(configs);
+            }
+            run_redact(&cli, &input)
         }
     }
-
-    // -- Event stream ----------- -------------------------------------------
-    if !dry_run {
-        std::io::copy(reader, writer).context("copying event stream")?;
-    }
-
-    Ok(redacted_fns)
 }
 
-// ---------------------------------------------------------------------------
-// main
-// ---------------------------------------------------------------------------
-
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-
+fn run_redact(cli: &Cli, input: &Path) -> Result<()> {
     // Validate input exists
-    if !cli.input.exists() {
-        bail!("input file not found: {}", cli.input.display());
+    if !input.exists() {
+        bail!("input file not found: {}", input.display());
     }
 
-    let output_path = resolve_output(&cli)?;
+    let output_path = resolve_output(cli, input)?;
 
     // Determine actual output: temp file for --in-place, path for normal
     let temp_path = if cli.in_place && !cli.dry_run {
-        let dir = cli
-            .input
+        let dir = input
             .parent()
             .unwrap_or_else(|| Path::new("."))
             .to_path_buf();
-        let stem = cli
-            .input
+        let stem = input
             .file_stem()
             .context("input has no file stem")?
             .to_string_lossy();
@@ -244,59 +622,156 @@ fn main() -> Result<()> {
     };
 
     // Open input
-    let input_file = File::open(&cli.input)
-        .with_context(|| format!("opening input: {}", cli.input.display()))?;
+    let input_file =
+        File::open(input).with_context(|| format!("opening input: {}", input.display()))?;
     let mut reader = BufReader::with_capacity(BUF_SIZE, input_file);
 
     // Open output / temp
     let effective_out = temp_path.as_ref().or(output_path.as_ref());
 
-    let redacted: Vec<String>;
+    let pseudonyms = match &cli.pseudonym_key {
+        Some(path) => {
+            let key = fs::read(path)
+                .with_context(|| format!("reading pseudonym key: {}", path.display()))?;
+            Some(Pseudonymizer::new(&key)?)
+        }
+        None => None,
+    };
 
-    if let Some(out) = effective_out {
-        let out_file =
-            File::create(out).with_context(|| format!("creating output: {}", out.display()))?;
-        let mut writer = BufWriter::with_capacity(BUF_SIZE, out_file);
+    let sidecar_key = match &cli.sidecar {
+        Some(_) if !cli.dry_run => Some(cli.sidecar_key()?),
+        _ => None,
+    };
 
-        redacted = process(
-            &mut reader,
-            &mut writer,
-            false,
-            &cli.file_markers,
-            &cli.fn_markers,
-        )?;
+    let options = Options {
+        rules: cli.rules()?,
+        mode: cli.mode.unwrap_or_default(),
+        zones: if cli.drop_descendants {
+            ZonePolicy::DropTree
+        } else if cli.drop_zones {
+            ZonePolicy::Drop
+        } else {
+            ZonePolicy::Keep
+        },
+        header: cli.header_scrub(),
+        srcloc: SrcLocScrub {
+            line: cli.redacted_line.clone().unwrap_or(FieldPolicy::Blank),
+            color: cli.redacted_color.clone().unwrap_or(FieldPolicy::Blank),
+        },
+        pseudonyms,
+        pseudonymize_all: cli.pseudonymize,
+        sidecar: sidecar_key.is_some(),
+    };
+    // The sidecar goes to a temp file too, and both are only moved into
+    // place once written, so a failure never leaves the input replaced
+    // without a way to restore it.
+    let sidecar_tmp = match &cli.sidecar {
+        Some(path) if sidecar_key.is_some() => {
+            let name = path
+                .file_name()
+                .context("sidecar path has no file name")?
+                .to_string_lossy();
+            Some(path.with_file_name(format!("{name}.tmp_{}", std::process::id())))
+        }
+        _ => None,
+    };
+    let remove_temps = || {
+        for tmp in temp_path.iter().chain(&sidecar_tmp) {
+            let _ = fs::remove_file(tmp);
+        }
+    };
 
-        writer.flush().context("flushing output")?;
-    } else {
-        // dry_run
-        redacted = process(
-            &mut reader,
-            &mut std::io::sink(),
-            cli.dry_run,
-            &cli.file_markers,
-            &cli.fn_markers,
-        )?;
+    let written = (|| -> Result<Report> {
+        let report = if let Some(out) = effective_out {
+            let out_file =
+                File::create(out).with_context(|| format!("creating output: {}", out.display()))?;
+            let mut writer = BufWriter::with_capacity(BUF_SIZE, out_file);
+
+            let report = redact::process(&mut reader, &mut writer, false, &options)?;
+
+            writer.flush().context("flushing output")?;
+            report
+        } else {
+            // dry_run
+            redact::process(&mut reader, &mut std::io::sink(), cli.dry_run, &options)?
+        };
+
+        if let (Some(tmp), Some(key)) = (&sidecar_tmp, &sidecar_key) {
+            let file = File::create(tmp)
+                .with_context(|| format!("creating sidecar: {}", tmp.display()))?;
+            report
+                .sidecar
+                .as_ref()
+                .expect("sidecar recorded when requested")
+                .write_encrypted(BufWriter::new(file), key)
+                .with_context(|| format!("writing sidecar: {}", tmp.display()))?;
+        }
+        Ok(report)
+    })();
+    let report = written.inspect_err(|_| remove_temps())?;
+
+    if let (Some(tmp), Some(path)) = (&sidecar_tmp, &cli.sidecar) {
+        fs::rename(tmp, path)
+            .with_context(|| format!("renaming temp file {} to {}", tmp.display(), path.display()))
+            .inspect_err(|_| remove_temps())?;
     }
 
     // rename for --in-place
     if let Some(tmp) = &temp_path {
-        fs::rename(tmp, &cli.input).with_context(|| {
-            format!(
-                "renaming temp file {} over {}",
-                tmp.display(),
-                cli.input.display()
-            )
-        })?;
+        fs::rename(tmp, input)
+            .with_context(|| {
+                format!(
+                    "renaming temp file {} over {}",
+                    tmp.display(),
+                    input.display()
+                )
+            })
+            .inspect_err(|_| remove_temps())?;
+    }
+
+    if cli.show_header {
+        println!("{}", report.header);
     }
 
+    let redacted = &report.redacted;
     let count = redacted.len();
+    for path in &cli.configs {
+        println!("Using config: {}", path.display());
+    }
+    if !report.scrubbed.is_empty() {
+        let fields = report.scrubbed.join(", ");
+        if cli.dry_run {
+            println!("Dry run: would scrub header fields: {fields}");
+        } else {
+            println!("Scrubbed header fields: {fields}");
+        }
+    }
     if cli.dry_run {
         if count == 0 {
             println!("Dry run: no source locations would be redacted.");
         } else {
             println!("Dry run: would redact {count} source locations:");
-            for f in &redacted {
-                println!("  {f}");
+            for r in redacted {
+                println!(
+                    "  {} ({} by {})",
+                    display_name(&r.original),
+                    r.action,
+                    r.rule
+                );
+            }
+        }
+        if !report.excepted.is_empty() {
+            println!(
+                "Dry run: {} source locations kept by exceptions:",
+                report.excepted.len()
+            );
+            for e in &report.excepted {
+                println!(
+                    "  {} (matched {}, kept by {})",
+                    display_name(&e.srcloc),
+                    e.marker,
+                    e.exception
+                );
             }
         }
     } else {
@@ -305,9 +780,20 @@ fn main() -> Result<()> {
         } else {
             println!("Redacted {count} source locations.");
         }
+        if !report.excepted.is_empty() {
+            println!(
+                "Kept {} source locations by exception.",
+                report.excepted.len()
+            );
+        }
+        if let Some(events) = report.events
+            && report.events_dropped > 0
+        {
+            println!("Dropped {} of {events} events.", report.events_dropped);
+        }
 
         let final_out = if cli.in_place {
-            cli.input.display().to_string()
+            input.display().to_string()
         } else {
             output_path
                 .as_ref()
@@ -315,7 +801,201 @@ fn main() -> Result<()> {
                 .unwrap_or_default()
         };
         println!("Output: {final_out}");
+        if let Some(path) = &cli.sidecar {
+            println!("Sidecar: {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// How a srcloc is listed in the summary: its function, or its zone name if
+/// it has none.
+fn display_name(loc: &SrcLoc) -> &str {
+    if loc.function.is_empty() {
+        &loc.name
+    } else {
+        &loc.function
+    }
+}
+
+fn run_unredact(args: &UnredactArgs) -> Result<()> {
+    if !args.input.exists() {
+        bail!("input file not found: {}", args.input.display());
+    }
+
+    let output = match &args.output {
+        Some(p) => p.clone(),
+        None => {
+            let dir = args.input.parent().unwrap_or_else(|| Path::new("."));
+            let stem = args
+                .input
+                .file_stem()
+                .context("input has no file stem")?
+                .to_string_lossy();
+            let stem = stem.strip_suffix(".redacted").unwrap_or(&stem);
+            dir.join(format!("{stem}.unredacted.utracy"))
+        }
+    };
+    let canonical_in = fs::canonicalize(&args.input).unwrap_or_else(|_| args.input.clone());
+    if canonical_in == fs::canonicalize(&output).unwrap_or_else(|_| output.clone()) {
+        bail!("output path is the same as the input file");
+    }
+
+    let sidecar_file = File::open(&args.sidecar)
+        .with_context(|| format!("opening sidecar: {}", args.sidecar.display()))?;
+    let sidecar = match &args.identity {
+        Some(path) => {
+            let identities = age::IdentityFile::from_file(path.to_string_lossy().into_owned())
+                .with_context(|| format!("reading identity file: {}", path.display()))?
+                .into_identities()
+                .context("loading identities")?;
+            Sidecar::read_with_identities(sidecar_file, &identities)?
+        }
+        None => match std::env::var(SIDECAR_PASSPHRASE_ENV) {
+            Ok(pass) if !pass.is_empty() => {
+                Sidecar::read_with_passphrase(sidecar_file, pass.into())?
+            }
+            _ => bail!("unredact needs --identity or a passphrase in ${SIDECAR_PASSPHRASE_ENV}"),
+        },
+    };
+
+    let input_file = File::open(&args.input)
+        .with_context(|| format!("opening input: {}", args.input.display()))?;
+    let mut reader = BufReader::with_capacity(BUF_SIZE, input_file);
+    let out_file =
+        File::create(&output).with_context(|| format!("creating output: {}", output.display()))?;
+    let mut writer = BufWriter::with_capacity(BUF_SIZE, out_file);
+
+    let result = unredact::process(&mut reader, &mut writer, &sidecar)
+        .and_then(|()| writer.flush().context("flushing output"));
+    if let Err(e) = result {
+        drop(writer);
+        let _ = fs::remove_file(&output);
+        return Err(e);
     }
 
+    println!(
+        "Restored {} source locations; checksum matches the original.",
+        sidecar.entries.len()
+    );
+    println!("Output: {}", output.display());
     Ok(())
 }
+
+fn run_presets(args: &PresetsArgs) -> Result<()> {
+    match &args.name {
+        Some(name) => print!("{}", Preset::find(name)?.source),
+        None => {
+            let width = PRESETS.iter().map(|p| p.name.len()).max().unwrap_or(0);
+            for preset in PRESETS {
+                println!("{:width$}  {}", preset.name, preset.description);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let argv = ["utracy-redact", "in.utracy"]
+            .into_iter()
+            .chain(args.iter().copied());
+        let mut cli = Cli::try_parse_from(argv).unwrap();
+        cli.add_default_markers();
+        cli
+    }
+
+    fn with_config(args: &[&str], config: &str) -> Cli {
+        let mut cli = parse(args);
+        cli.apply_configs(vec![Config {
+            path: PathBuf::from(".utracy-redact.toml"),
+            rules: Rules::new(),
+            settings: toml::from_str(config).unwrap(),
+        }]);
+        cli
+    }
+
+    const SWITCHES: &str = r#"
+        default-deny = true
+        public-root = ["code/"]
+        drop-zones = true
+        drop-descendants = true
+        scrub-header = true
+        pseudonymize = true
+    "#;
+
+    #[test]
+    fn config_switches_apply() {
+        let cli = with_config(&[], SWITCHES);
+        assert!(cli.default_deny());
+        assert!(cli.drop_zones && cli.drop_descendants);
+        assert!(cli.scrub_header);
+        assert!(cli.pseudonymize);
+    }
+
+    #[test]
+    fn command_line_turns_config_switches_off() {
+        let cli = with_config(
+            &[
+                "--no-default-deny",
+                "--no-drop-zones",
+                "--no-scrub-header",
+                "--no-pseudonymize",
+            ],
+            SWITCHES,
+        );
+        assert!(!cli.default_deny());
+        assert!(!cli.drop_zones && !cli.drop_descendants);
+        assert!(!cli.scrub_header);
+        assert!(!cli.pseudonymize);
+
+        // The last of a switch and its negation wins.
+        let cli = with_config(&["--no-drop-zones", "--drop-zones"], SWITCHES);
+        assert!(cli.drop_zones);
+    }
+
+    #[test]
+    fn command_line_values_win() {
+        let config = r#"
+            mode = "drop"
+            redacted-line = "keep"
+            rules = ["config.toml"]
+        "#;
+        let cli = with_config(&["--mode", "merge", "--rules", "cli.toml"], config);
+        assert_eq!(cli.mode, Some(Mode::Merge));
+        assert_eq!(cli.redacted_line, Some(FieldPolicy::Keep));
+        assert_eq!(
+            cli.rules,
+            [PathBuf::from("cli.toml"), PathBuf::from("config.toml")]
+        );
+    }
+
+    #[test]
+    fn default_markers_are_per_field() {
+        let cli = parse(&["--file-marker", "private"]);
+        assert_eq!(cli.file_markers, ["private"]);
+        assert_eq!(cli.fn_markers, DEFAULT_FN_MARKERS);
+
+        let cli = parse(&["--fn-marker", "internal", "--codebase", "goon"]);
+        assert_eq!(cli.file_markers, DEFAULT_FILE_MARKERS);
+        assert_eq!(cli.fn_markers, ["internal"]);
+
+        let cli = parse(&["--no-default-markers"]);
+        assert!(cli.file_markers.is_empty() && cli.fn_markers.is_empty());
+    }
+
+    #[test]
+    fn config_markers_add_to_defaults() {
+        let config = r#"fn-marker = ["admin"]"#;
+        let cli = with_config(&[], config);
+        assert_eq!(cli.file_markers, DEFAULT_FILE_MARKERS);
+        assert_eq!(cli.fn_markers, ["secret", "admin"]);
+
+        let cli = with_config(&["--fn-marker", "internal"], config);
+        assert_eq!(cli.fn_markers, ["internal", "admin"]);
+    }
+}
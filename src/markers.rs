@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+use crate::srcloc::SrcLoc;
+
+/// A srcloc text field a marker is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Function,
+    File,
+}
+
+impl Field {
+    /// The field's text. File paths are normalized to `/` separators, since
+    /// Windows-built servers emit backslashes.
+    fn get(self, srcloc: &SrcLoc) -> Cow<'_, str> {
+        match self {
+            Field::Name => Cow::Borrowed(&srcloc.name),
+            Field::Function => Cow::Borrowed(&srcloc.function),
+            Field::File => normalize_path(&srcloc.file),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Field::Name => "name",
+            Field::Function => "function",
+            Field::File => "file",
+        })
+    }
+}
+
+/// Replace `\` path separators with `/`.
+pub fn normalize_path(path: &str) -> Cow<'_, str> {
+    if path.contains('\\') {
+        Cow::Owned(path.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// How a marker matches its field.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Substring; when case-insensitive, `text` is stored case-folded.
+    Substring { text: String, case_sensitive: bool },
+    /// Regular expression, searched anywhere in the field unless anchored.
+    Regex { regex: Regex, case_sensitive: bool },
+    /// Glob over the whole field, where `*` stays within one path component
+    /// and `**` spans any number of them; when case-insensitive, it's built
+    /// from the case-folded glob.
+    Glob {
+        glob: GlobMatcher,
+        case_sensitive: bool,
+    },
+    /// The whole field equals one of these strings; when case-insensitive,
+    /// they're stored case-folded.
+    OneOf {
+        values: HashSet<String>,
+        case_sensitive: bool,
+    },
+}
+
+impl Pattern {
+    /// Case-insensitive substring.
+    pub fn substring(s: &str) -> Self {
+        Self::substring_with(s, false)
+    }
+
+    pub fn substring_with(s: &str, case_sensitive: bool) -> Self {
+        Pattern::Substring {
+            text: if case_sensitive {
+                s.to_owned()
+            } else {
+                fold_case(s)
+            },
+            case_sensitive,
+        }
+    }
+
+    /// Case-sensitive regex, unless it sets `(?i)`.
+    pub fn regex(s: &str) -> Result<Self> {
+        Self::regex_with(s, true)
+    }
+
+    pub fn regex_with(s: &str, case_sensitive: bool) -> Result<Self> {
+        let regex = RegexBuilder::new(s)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .with_context(|| format!("invalid regex {s:?}"))?;
+        Ok(Pattern::Regex {
+            regex,
+            case_sensitive,
+        })
+    }
+
+    /// Case-insensitive glob.
+    pub fn glob(s: &str) -> Result<Self> {
+        Self::glob_with(s, false)
+    }
+
+    /// Backslashes in `s` are treated as path separators, not escapes.
+    pub fn glob_with(s: &str, case_sensitive: bool) -> Result<Self> {
+        let mut glob = normalize_path(s);
+        if !case_sensitive {
+            glob = Cow::Owned(fold_case(&glob));
+        }
+        let glob = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .backslash_escape(false)
+            .build()
+            .with_context(|| format!("invalid glob {s:?}"))?;
+        Ok(Pattern::Glob {
+            glob: glob.compile_matcher(),
+            case_sensitive,
+        })
+    }
+
+    /// Case-insensitive match against any of `values` in full.
+    pub fn one_of<I: IntoIterator<Item = S>, S: AsRef<str>>(values: I) -> Self {
+        Self::one_of_with(values, false)
+    }
+
+    pub fn one_of_with<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        values: I,
+        case_sensitive: bool,
+    ) -> Self {
+        Pattern::OneOf {
+            values: values
+                .into_iter()
+                .map(|v| {
+                    if case_sensitive {
+                        v.as_ref().to_owned()
+                    } else {
+                        fold_case(v.as_ref())
+                    }
+                })
+                .collect(),
+            case_sensitive,
+        }
+    }
+
+    /// Parse `re:<REGEX>`, `glob:<GLOB>` or a plain substring.
+    pub fn parse(s: &str) -> Result<Self> {
+        Self::parse_with(s, None)
+    }
+
+    /// Like [`Pattern::parse`], with `case_sensitive` overriding the default
+    /// of the pattern kind.
+    pub fn parse_with(s: &str, case_sensitive: Option<bool>) -> Result<Self> {
+        if let Some(re) = s.strip_prefix("re:") {
+            Self::regex_with(re, case_sensitive.unwrap_or(true))
+        } else if let Some(glob) = s.strip_prefix("glob:") {
+            Self::glob_with(glob, case_sensitive.unwrap_or(false))
+        } else {
+            Ok(Self::substring_with(s, case_sensitive.unwrap_or(false)))
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Substring {
+                text: m,
+                case_sensitive: true,
+            } => text.contains(m.as_str()),
+            Pattern::Substring { text: m, .. } => fold_case(text).contains(m.as_str()),
+            Pattern::Regex { regex, .. } => regex.is_match(text),
+            Pattern::Glob {
+                glob,
+                case_sensitive: true,
+            } => glob.is_match(text),
+            Pattern::Glob { glob, .. } => glob.is_match(fold_case(text)),
+            Pattern::OneOf {
+                values,
+                case_sensitive: true,
+            } => values.contains(text),
+            Pattern::OneOf { values, .. } => values.contains(&fold_case(text)),
+        }
+    }
+}
+
+/// Unicode default case folding, so e.g. `É` matches `é` and `ß` matches
+/// `SS`.
+pub(crate) fn fold_case(s: &str) -> String {
+    caseless::default_case_fold_str(s)
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only casing that differs from the kind's default is shown.
+        match self {
+            Pattern::Substring {
+                text,
+                case_sensitive,
+            } => {
+                write!(f, "{text:?}")?;
+                if *case_sensitive {
+                    f.write_str(" (case-sensitive)")?;
+                }
+            }
+            Pattern::Regex {
+                regex,
+                case_sensitive,
+            } => {
+                write!(f, "re:{:?}", regex.as_str())?;
+                if !case_sensitive {
+                    f.write_str(" (case-insensitive)")?;
+                }
+            }
+            Pattern::Glob {
+                glob,
+                case_sensitive,
+            } => {
+                write!(f, "glob:{:?}", glob.glob().glob())?;
+                if *case_sensitive {
+                    f.write_str(" (case-sensitive)")?;
+                }
+            }
+            Pattern::OneOf {
+                values,
+                case_sensitive,
+            } => {
+                write!(f, "one of {} values", values.len())?;
+                if *case_sensitive {
+                    f.write_str(" (case-sensitive)")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A pattern on one srcloc field.
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub field: Field,
+    pub pattern: Pattern,
+}
+
+impl Marker {
+    pub fn new(field: Field, pattern: Pattern) -> Self {
+        Self { field, pattern }
+    }
+
+    pub fn is_match(&self, srcloc: &SrcLoc) -> bool {
+        self.pattern.is_match(&self.field.get(srcloc))
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.field, self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_patterns_fold_unicode() {
+        let patterns = [
+            Pattern::substring("straße"),
+            Pattern::glob("**/straße/*.dm").unwrap(),
+            Pattern::one_of(["code/straße/café.dm"]),
+        ];
+        for pattern in &patterns {
+            for text in ["code/STRASSE/CAFÉ.dm", "code/Straße/café.dm"] {
+                assert!(pattern.is_match(text), "{pattern} on {text:?}");
+            }
+            assert!(!pattern.is_match("code/strase/cafe.dm"), "{pattern}");
+        }
+        assert!(Pattern::substring("CAFÉ").is_match("café"));
+        assert!(Pattern::glob("É*").unwrap().is_match("éclair"));
+        assert!(Pattern::one_of(["ÉCLAIR"]).is_match("éclair"));
+    }
+
+    #[test]
+    fn case_sensitive_patterns_dont_fold() {
+        let patterns = [
+            Pattern::substring_with("straße", true),
+            Pattern::glob_with("**/straße/*.dm", true).unwrap(),
+            Pattern::one_of_with(["code/straße/café.dm"], true),
+        ];
+        for pattern in &patterns {
+            assert!(pattern.is_match("code/straße/café.dm"), "{pattern}");
+            assert!(!pattern.is_match("code/STRASSE/CAFÉ.dm"), "{pattern}");
+        }
+    }
+}
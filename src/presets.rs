@@ -0,0 +1,40 @@
+use anyhow::{Context, Result, bail};
+
+use crate::rules::Rules;
+
+/// A curated rule set for a known codebase.
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The rules, in the TOML format read by [`Rules::from_toml`].
+    pub source: &'static str,
+}
+
+/// Every built-in preset.
+pub const PRESETS: &[Preset] = &[Preset {
+    name: "goonstation",
+    description: "The default markers, plus everything in Goonstation's +secret submodule",
+    source: include_str!("../presets/goonstation.toml"),
+}];
+
+impl Preset {
+    /// The built-in preset called `name`.
+    pub fn find(name: &str) -> Result<&'static Preset> {
+        match PRESETS.iter().find(|p| p.name.eq_ignore_ascii_case(name)) {
+            Some(preset) => Ok(preset),
+            None => {
+                let names: Vec<_> = PRESETS.iter().map(|p| p.name).collect();
+                bail!(
+                    "unknown preset {name:?}, expected one of {}",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    pub fn rules(&self) -> Result<Rules> {
+        let origin = format!("preset {}", self.name);
+        Rules::from_toml(self.source, &origin).with_context(|| format!("loading {origin}"))
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::srcloc::SrcLoc;
+
+/// Number of hex digits kept from the keyed hash. At 48 bits, two of even a
+/// few thousand secret procs are unlikely to share a token.
+const TOKEN_HEX_LEN: usize = 12;
+
+/// Derives stable `<redacted:3f9a1c07b2e4>` tokens for secret srclocs from a
+/// local secret key.
+///
+/// The same proc always gets the same token under the same key, so it can
+/// be followed across captures, but the token reveals nothing about the name
+/// without the key.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    mac: Hmac<Sha256>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.is_empty() {
+            bail!("pseudonym key is empty");
+        }
+        Ok(Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+        })
+    }
+
+    /// The token for `srcloc`, keyed on its function (or its name, for
+    /// zones without one).
+    pub fn token(&self, srcloc: &SrcLoc) -> String {
+        let identity = if srcloc.function.is_empty() {
+            &srcloc.name
+        } else {
+            &srcloc.function
+        };
+
+        let mut mac = self.mac.clone();
+        mac.update(identity.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let hex: String = digest
+            .iter()
+            .take(TOKEN_HEX_LEN / 2)
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("<redacted:{hex}>")
+    }
+}
+
+impl std::fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pseudonymizer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proc(function: &str) -> SrcLoc {
+        SrcLoc {
+            name: "plan".into(),
+            function: function.into(),
+            ..SrcLoc::default()
+        }
+    }
+
+    #[test]
+    fn tokens_are_keyed_and_stable() {
+        let a = Pseudonymizer::new(b"key a").unwrap();
+        let plan = a.token(&proc("/datum/secret/proc/plan"));
+        assert_eq!(plan.len(), "<redacted:>".len() + TOKEN_HEX_LEN);
+        assert!(
+            plan[10..plan.len() - 1]
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit())
+        );
+
+        // Same key, new instance: same token.
+        let again = Pseudonymizer::new(b"key a").unwrap();
+        assert_eq!(again.token(&proc("/datum/secret/proc/plan")), plan);
+        assert_ne!(a.token(&proc("/datum/secret/proc/scheme")), plan);
+
+        let b = Pseudonymizer::new(b"key b").unwrap();
+        assert_ne!(b.token(&proc("/datum/secret/proc/plan")), plan);
+
+        // Zones without a function fall back to their name.
+        assert_eq!(a.token(&proc("")), a.token(&proc("plan")));
+    }
+
+    #[test]
+    fn empty_key_is_an_error() {
+        assert!(Pseudonymizer::new(b"").is_err());
+    }
+}
@@ -0,0 +1,660 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result, bail};
+
+use crate::event::{Event, EventReader, EventWriter};
+use crate::filter::{EventFilter, ZoneAction};
+use crate::header::Header;
+use crate::io::HashingReader;
+use crate::pseudonym::Pseudonymizer;
+use crate::rules::{Action, Rules, Verdict};
+use crate::scrub::{FieldPolicy, HeaderScrub, SrcLocScrub};
+use crate::sidecar::Sidecar;
+use crate::srcloc::{self, SrcLoc};
+
+/// Replacement text for redacted srcloc fields.
+pub const REDACTED: &str = "<redacted>";
+
+/// How srclocs matched by an [`Action::Redact`] rule are represented in the
+/// output table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Keep each entry in place with its text replaced by [`REDACTED`].
+    #[default]
+    Placeholder,
+    /// Remove the entries from the table, renumber the remaining srclocs and
+    /// drop the zones that referenced them.
+    Drop,
+    /// Collapse every secret srcloc into one shared [`REDACTED`] entry and
+    /// point their zones at it.
+    Merge,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "placeholder" => Ok(Mode::Placeholder),
+            "drop" => Ok(Mode::Drop),
+            "merge" => Ok(Mode::Merge),
+            _ => bail!("expected `placeholder`, `drop` or `merge`, got {s:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Placeholder => "placeholder",
+            Mode::Drop => "drop",
+            Mode::Merge => "merge",
+        })
+    }
+}
+
+/// What happens to the zones (begin/end events) of secret srclocs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZonePolicy {
+    /// Keep them, so their timing stays visible under the redacted srcloc.
+    #[default]
+    Keep,
+    /// Drop them; zones nested inside stay.
+    Drop,
+    /// Drop them along with every zone nested inside.
+    DropTree,
+}
+
+/// How one redacted srcloc ended up in the output table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Kept in place with its text replaced.
+    Placeholder,
+    /// Removed from the table, along with its zones.
+    Dropped,
+    /// Folded into the shared [`REDACTED`] entry.
+    Merged,
+}
+
+/// Settings for a [`process`] run.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Which srclocs are secret, and what to do with them.
+    pub rules: Rules,
+    /// How srclocs matched by an [`Action::Redact`] rule are written.
+    pub mode: Mode,
+    /// What happens to the zones of secret srclocs. [`Mode::Drop`] always
+    /// drops them.
+    pub zones: ZonePolicy,
+    /// Which identifying header fields to scrub.
+    pub header: HeaderScrub,
+    /// What to do with the line and color of redacted srclocs.
+    pub srcloc: SrcLocScrub,
+    /// Key for replacing redacted text with a token instead of [`REDACTED`];
+    /// required by [`Action::Pseudonymize`] rules and `pseudonymize_all`.
+    pub pseudonyms: Option<Pseudonymizer>,
+    /// Pseudonymize every placeholder, not just those of
+    /// [`Action::Pseudonymize`] rules. Never applies to the shared entry of
+    /// [`Mode::Merge`].
+    pub pseudonymize_all: bool,
+    /// Record everything needed to restore the input in [`Report::sidecar`].
+    pub sidecar: bool,
+}
+
+/// A srcloc that was redacted.
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    /// Index in the input srcloc table.
+    pub index: u32,
+    /// The srcloc as it was in the input.
+    pub original: SrcLoc,
+    /// Label of the rule that matched.
+    pub rule: String,
+    pub action: Action,
+    pub disposition: Disposition,
+}
+
+/// A srcloc that matched a rule but was kept by an earlier keep rule.
+#[derive(Debug, Clone)]
+pub struct Exception {
+    /// Index in the input srcloc table.
+    pub index: u32,
+    pub srcloc: SrcLoc,
+    /// Label of the rule that was overridden.
+    pub marker: String,
+    /// Label of the keep rule that overrode it.
+    pub exception: String,
+}
+
+/// Summary of a [`process`] run.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The input file header, before scrubbing.
+    pub header: Header,
+    /// Names of the header fields changed by scrubbing.
+    pub scrubbed: Vec<&'static str>,
+    /// The redacted srclocs, in table order.
+    pub redacted: Vec<Redaction>,
+    /// Srclocs kept by an exception, in table order.
+    pub excepted: Vec<Exception>,
+    /// Number of events in the input event stream, or `None` if nothing
+    /// had to change and the stream was copied without decoding it.
+    pub events: Option<u64>,
+    /// Number of those events left out of the output.
+    pub events_dropped: u64,
+    /// With [`Options::sidecar`], what the output needs to be restored to
+    /// the input byte-for-byte.
+    pub sidecar: Option<Sidecar>,
+}
+
+/// Copy a .utracy file from `reader` to `writer`, redacting every secret
+/// srcloc according to [`Options::mode`] and scrubbing the header as
+/// configured.
+///
+/// The output uses the same format version as the input. With `dry_run`
+/// nothing is written.
+pub fn process<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    dry_run: bool,
+    options: &Options,
+) -> Result<Report> {
+    if options.pseudonyms.is_none()
+        && let Some(rule) = options
+            .rules
+            .iter()
+            .find(|r| r.action == Action::Pseudonymize)
+    {
+        bail!(
+            "{} pseudonymizes, but no pseudonym key was given",
+            rule.label
+        );
+    }
+    if options.pseudonymize_all && options.pseudonyms.is_none() {
+        bail!("pseudonymizing every redaction needs a pseudonym key");
+    }
+
+    let mut reader = HashingReader::new(reader, options.sidecar);
+
+    // -- Header (1200 bytes - calculated) -----------------------------------
+    let header = Header::read(&mut reader)?;
+    let version = header.format_version()?;
+
+    let mut scrubbed_header = header.clone();
+    let scrubbed = options.header.apply(&mut scrubbed_header);
+
+    if !dry_run {
+        scrubbed_header.write(writer)?;
+    }
+
+    // -- Srcloc table --------------------------------------------------------
+    // Buffered, since dropping entries changes the count written before it.
+    let srcloc_count = srcloc::read_count(&mut reader)?;
+    let mut table = Vec::new();
+    let mut actions = Vec::new();
+    let mut redacted = Vec::new();
+    let mut excepted = Vec::new();
+    let secret_zone = |srcloc| match options.zones {
+        ZonePolicy::Keep => ZoneAction::Keep {
+            srcloc,
+            color: options.srcloc.color == FieldPolicy::Keep,
+        },
+        ZonePolicy::Drop | ZonePolicy::DropTree => ZoneAction::Drop,
+    };
+    // Index of the shared entry in merge mode, placed where the first secret
+    // srcloc was.
+    let mut merged = None;
+
+    for index in 0..srcloc_count {
+        let mut loc = SrcLoc::read(&mut reader, version)?;
+
+        let rule = match options.rules.check(&loc) {
+            Verdict::Matched { rule } if rule.action != Action::Keep => Some(rule),
+            Verdict::Excepted { rule, overridden } => {
+                excepted.push(Exception {
+                    index,
+                    srcloc: loc.clone(),
+                    marker: overridden.label.clone(),
+                    exception: rule.label.clone(),
+                });
+                None
+            }
+            _ => None,
+        };
+        let Some(rule) = rule else {
+            actions.push(ZoneAction::Keep {
+                srcloc: table.len() as u32,
+                color: true,
+            });
+            table.push(loc);
+            continue;
+        };
+
+        // Only plain redact rules follow the global mode.
+        let disposition = match (rule.action, options.mode) {
+            (Action::Drop, _) | (Action::Redact, Mode::Drop) => Disposition::Dropped,
+            (Action::Redact, Mode::Merge) => Disposition::Merged,
+            _ => Disposition::Placeholder,
+        };
+        redacted.push(Redaction {
+            index,
+            original: loc.clone(),
+            rule: rule.label.clone(),
+            action: rule.action,
+            disposition,
+        });
+        match disposition {
+            Disposition::Placeholder => {
+                let text = match &options.pseudonyms {
+                    Some(p) if options.pseudonymize_all || rule.action == Action::Pseudonymize => {
+                        p.token(&loc)
+                    }
+                    _ => REDACTED.to_owned(),
+                };
+                loc.name = text.clone();
+                loc.function = text.clone();
+                loc.file = text;
+                options.srcloc.apply(&mut loc);
+            }
+            Disposition::Dropped => {
+                actions.push(ZoneAction::Drop);
+                continue;
+            }
+            Disposition::Merged => {
+                if let Some(index) = merged {
+                    actions.push(secret_zone(index));
+                    continue;
+                }
+                merged = Some(table.len() as u32);
+                loc = SrcLoc {
+                    name: REDACTED.to_owned(),
+                    function: REDACTED.to_owned(),
+                    file: REDACTED.to_owned(),
+                    ..SrcLoc::default()
+                };
+                options.srcloc.apply(&mut loc);
+            }
+        }
+
+        actions.push(secret_zone(table.len() as u32));
+        table.push(loc);
+    }
+
+    if !dry_run {
+        srcloc::write_count(writer, table.len() as u32)?;
+        for loc in &table {
+            loc.write(writer, version)?;
+        }
+    }
+
+    // -- Event stream --------------------------------------------------------
+    let mut events_dropped = 0;
+    // Sidecar bookkeeping: dropped events keyed by their output position,
+    // and the input srcloc of every zone pointed at the merged entry.
+    let mut dropped = Vec::new();
+    let mut merged_zones = Vec::new();
+
+    // Only decoded when some zone has to be renumbered or left out;
+    // otherwise the stream is copied through byte for byte.
+    let untouched = actions.iter().enumerate().all(|(index, action)| {
+        *action
+            == ZoneAction::Keep {
+                srcloc: index as u32,
+                color: true,
+            }
+    });
+    let events = if untouched {
+        if dry_run {
+            io::copy(&mut reader, &mut io::sink())
+        } else {
+            io::copy(&mut reader, writer)
+        }
+        .context("copying event stream")?;
+        None
+    } else {
+        let mut events = EventReader::new(&mut reader, version);
+        let mut out = EventWriter::new(writer, version);
+        let mut filter = EventFilter::new(actions, options.zones == ZonePolicy::DropTree);
+        for (index, event) in (&mut events).enumerate() {
+            let event = event?;
+
+            if let Event::ZoneBegin { srcloc, .. } = event
+                && srcloc >= srcloc_count
+            {
+                bail!(
+                    "event #{index} references srcloc {srcloc}, but the table only has {srcloc_count} entries"
+                );
+            }
+
+            match filter.filter(event) {
+                Some(kept) => {
+                    if let Event::ZoneBegin { srcloc: new, .. } = kept
+                        && options.sidecar
+                        && Some(new) == merged
+                        && let Event::ZoneBegin { srcloc: old, .. } = event
+                    {
+                        merged_zones.push(old);
+                    }
+                    if !dry_run {
+                        out.write(&kept)?;
+                    }
+                }
+                None => {
+                    if options.sidecar {
+                        dropped.push((index as u64 - events_dropped, event));
+                    }
+                    events_dropped += 1;
+                }
+            }
+        }
+        Some(events.decoded())
+    };
+
+    let sidecar = reader.finish().map(|checksum| Sidecar {
+        checksum,
+        header: header.clone(),
+        srcloc_count,
+        entries: redacted
+            .iter()
+            .map(|r| (r.index, r.disposition, r.original.clone()))
+            .collect(),
+        dropped_events: dropped,
+        merged_zones,
+    });
+
+    Ok(Report {
+        header,
+        scrubbed,
+        redacted,
+        excepted,
+        events,
+        events_dropped,
+        sidecar,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::header::{FILE_SIGNATURE, HEADER_SIZE};
+    use crate::markers::{Field, Pattern};
+    use crate::rules::Rule;
+    use crate::version::Version;
+
+    /// The srclocs of [`capture`]: 1 and 3 are secret, 4 is admin-only.
+    pub(crate) fn srclocs() -> Vec<SrcLoc> {
+        let loc = |name: &str, file: &str, line| SrcLoc {
+            name: name.into(),
+            function: format!("/datum/proc/{name}"),
+            file: file.into(),
+            line,
+            color: 0x00AB_CDEF,
+        };
+        vec![
+            loc("tick", "code/controllers/master.dm", 10),
+            loc("plot", "code_secret/plot.dm", 20),
+            loc("fire", "code/controllers/subsystem.dm", 30),
+            loc("scheme", "code_secret/scheme.dm", 40),
+            loc("ban", "code/modules/admin/ban.dm", 50),
+        ]
+    }
+
+    /// A small capture of [`srclocs`]. Thread 1 nests zones tick > plot >
+    /// fire > scheme, while thread 2 runs scheme and then ban.
+    pub(crate) fn capture(version: Version) -> Vec<u8> {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&FILE_SIGNATURE.to_le_bytes());
+        bytes[8..12].copy_from_slice(&version.as_u32().to_le_bytes());
+        let mut header = Header::from_bytes(&bytes).unwrap();
+        header.set_host_info("buildhost");
+        header.epoch = 1_700_000_000;
+        header.process_id = 4242;
+
+        let mut out = Vec::new();
+        header.write(&mut out).unwrap();
+        let locs = srclocs();
+        srcloc::write_count(&mut out, locs.len() as u32).unwrap();
+        for loc in &locs {
+            loc.write(&mut out, version).unwrap();
+        }
+
+        let begin = |tid, srcloc, timestamp| Event::ZoneBegin {
+            tid,
+            srcloc,
+            timestamp,
+        };
+        let end = |tid, timestamp| Event::ZoneEnd { tid, timestamp };
+        let color = |tid| Event::ZoneColor { tid, color: 0xFF };
+        let mut events = vec![
+            begin(1, 0, 1),
+            begin(2, 3, 2),
+            begin(1, 1, 3),
+            color(1),
+            begin(1, 2, 4),
+            color(1),
+            begin(1, 3, 5),
+            end(1, 6),
+            end(1, 7),
+            end(2, 8),
+            end(1, 9),
+            begin(2, 4, 10),
+            color(2),
+            end(2, 11),
+            end(1, 12),
+            Event::FrameMark { timestamp: 13 },
+        ];
+        if !version.has_zone_color() {
+            events.retain(|e| !matches!(e, Event::ZoneColor { .. }));
+        }
+        let mut writer = EventWriter::new(&mut out, version);
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        out
+    }
+
+    /// Redact `code_secret` files and drop the admin ones.
+    pub(crate) fn rules() -> Rules {
+        let mut rules = Rules::new();
+        rules.push(Rule::marker(
+            Field::File,
+            Pattern::substring("code_secret"),
+            Action::Redact,
+        ));
+        rules.push(Rule::marker(
+            Field::File,
+            Pattern::glob("code/modules/admin/**").unwrap(),
+            Action::Drop,
+        ));
+        rules
+    }
+
+    /// Only the redact rule of [`rules`], which keeps every zone.
+    pub(crate) fn secret_files() -> Rules {
+        let mut rules = Rules::new();
+        rules.push(Rule::marker(
+            Field::File,
+            Pattern::substring("code_secret"),
+            Action::Redact,
+        ));
+        rules
+    }
+
+    pub(crate) fn run(input: &[u8], options: &Options) -> (Report, Vec<u8>) {
+        let mut out = Vec::new();
+        let report = process(&mut &input[..], &mut out, false, options).unwrap();
+        (report, out)
+    }
+
+    /// The srcloc table and events of a `.utracy` file.
+    fn parse(bytes: &[u8]) -> (Vec<SrcLoc>, Vec<Event>) {
+        let mut r = bytes;
+        let version = Header::read(&mut r).unwrap().format_version().unwrap();
+        let count = srcloc::read_count(&mut r).unwrap();
+        let table = (0..count)
+            .map(|_| SrcLoc::read(&mut r, version).unwrap())
+            .collect();
+        let events = EventReader::new(r, version).collect::<Result<_>>().unwrap();
+        (table, events)
+    }
+
+    /// The srcloc of every zone begin, in stream order.
+    fn zones(events: &[Event]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                Event::ZoneBegin { srcloc, .. } => Some(*srcloc),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn ends(events: &[Event]) -> usize {
+        events
+            .iter()
+            .filter(|e| matches!(e, Event::ZoneEnd { .. }))
+            .count()
+    }
+
+    #[test]
+    fn drop_mode_renumbers() {
+        let options = Options {
+            rules: rules(),
+            mode: Mode::Drop,
+            ..Options::default()
+        };
+        let (report, out) = run(&capture(Version::V2), &options);
+        let (table, events) = parse(&out);
+
+        let names: Vec<_> = table.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["tick", "fire"]);
+        assert_eq!(zones(&events), [0, 1]);
+        assert_eq!(ends(&events), 2);
+        assert!(
+            report
+                .redacted
+                .iter()
+                .all(|r| r.disposition == Disposition::Dropped)
+        );
+        assert_eq!(report.events, Some(16));
+    }
+
+    #[test]
+    fn merge_mode_shares_one_entry() {
+        let options = Options {
+            rules: rules(),
+            mode: Mode::Merge,
+            ..Options::default()
+        };
+        let (_, out) = run(&capture(Version::V2), &options);
+        let (table, events) = parse(&out);
+
+        let names: Vec<_> = table.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["tick", REDACTED, "fire"]);
+        assert_eq!((table[1].line, table[1].color), (0, 0));
+        assert_eq!(zones(&events), [0, 1, 1, 2, 1]);
+        assert_eq!(ends(&events), 5);
+    }
+
+    #[test]
+    fn zone_policies() {
+        for (zones_policy, expected) in [
+            (ZonePolicy::Keep, &[0, 3, 1, 2, 3][..]),
+            (ZonePolicy::Drop, &[0, 2]),
+            (ZonePolicy::DropTree, &[0]),
+        ] {
+            let options = Options {
+                rules: rules(),
+                zones: zones_policy,
+                ..Options::default()
+            };
+            let (report, out) = run(&capture(Version::V2), &options);
+            let (_, events) = parse(&out);
+            assert_eq!(zones(&events), expected, "{zones_policy:?}");
+            assert_eq!(ends(&events), expected.len(), "{zones_policy:?}");
+            assert_eq!(
+                report.events.unwrap() - report.events_dropped,
+                events.len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn untouched_stream_is_copied_verbatim() {
+        // Placeholders that keep their zones and colors leave every event
+        // as it was, so the stream isn't decoded at all.
+        let mut table = Vec::new();
+        srcloc::write_count(&mut table, srclocs().len() as u32).unwrap();
+        for loc in &srclocs() {
+            loc.write(&mut table, Version::V2).unwrap();
+        }
+        let mut input = capture(Version::V2);
+        input.truncate(HEADER_SIZE + table.len());
+        input.extend([0xEE; 23]);
+        let options = Options {
+            rules: secret_files(),
+            srcloc: SrcLocScrub {
+                color: FieldPolicy::Keep,
+                ..SrcLocScrub::default()
+            },
+            ..Options::default()
+        };
+        let (report, out) = run(&input, &options);
+        assert_eq!(report.events, None);
+        assert!(out.ends_with(&[0xEE; 23]));
+
+        // Blanking their colors means decoding it, which fails on garbage.
+        let options = Options {
+            rules: secret_files(),
+            ..Options::default()
+        };
+        assert!(process(&mut &input[..], &mut Vec::new(), false, &options).is_err());
+    }
+
+    #[test]
+    fn only_pseudonymize_rules_get_tokens() {
+        let mut rules = Rules::new();
+        rules.push(Rule::marker(
+            Field::File,
+            Pattern::substring("scheme"),
+            Action::Pseudonymize,
+        ));
+        rules.extend(self::rules());
+        let mut options = Options {
+            rules,
+            pseudonyms: Some(Pseudonymizer::new(b"key").unwrap()),
+            ..Options::default()
+        };
+
+        let (_, out) = run(&capture(Version::V2), &options);
+        let (table, _) = parse(&out);
+        assert_eq!(table[1].name, REDACTED);
+        assert!(table[3].name.starts_with("<redacted:"));
+
+        options.pseudonymize_all = true;
+        let (_, out) = run(&capture(Version::V2), &options);
+        let (table, _) = parse(&out);
+        assert!(table[1].name.starts_with("<redacted:"));
+        assert_ne!(table[1].name, table[3].name);
+
+        options.pseudonyms = None;
+        let input = capture(Version::V2);
+        assert!(process(&mut &input[..], &mut std::io::sink(), true, &options).is_err());
+    }
+
+    #[test]
+    fn huge_srcloc_count_is_an_error() {
+        let mut input = capture(Version::V2);
+        input.truncate(HEADER_SIZE);
+        input.extend(u32::MAX.to_le_bytes());
+        let result = process(
+            &mut &input[..],
+            &mut std::io::sink(),
+            true,
+            &Options::default(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,453 @@
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result, bail};
+use serde::Deserialize;
+
+use crate::markers::{Field, Marker, Pattern, normalize_path};
+use crate::srcloc::SrcLoc;
+
+/// What happens to a srcloc matched by a [`Rule`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Leave the srcloc public.
+    Keep,
+    /// Redact it according to the run's [`crate::redact::Mode`].
+    Redact,
+    /// Replace its text with a keyed token, whatever the mode.
+    Pseudonymize,
+    /// Remove it and its zones, whatever the mode.
+    Drop,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Action::Keep),
+            "redact" => Ok(Action::Redact),
+            "pseudonymize" => Ok(Action::Pseudonymize),
+            "drop" => Ok(Action::Drop),
+            _ => bail!("expected `keep`, `redact`, `pseudonymize` or `drop`, got {s:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Keep => "keep",
+            Action::Redact => "redact",
+            Action::Pseudonymize => "pseudonymize",
+            Action::Drop => "drop",
+        })
+    }
+}
+
+/// One test a srcloc must pass for a [`Rule`] to match.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Field(Marker),
+    /// `srcloc.line` lies in this range.
+    Line(RangeInclusive<u32>),
+    /// At least one of these holds.
+    Any(Vec<Condition>),
+    /// This doesn't hold.
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// The srcloc's file starts with one of `roots`, which are
+    /// case-insensitive path prefixes such as `code/`.
+    pub fn file_under(roots: &[String]) -> Result<Self> {
+        let mut prefixes = Vec::with_capacity(roots.len());
+        for root in roots {
+            let prefix = format!("^{}", regex::escape(&normalize_path(root)));
+            let pattern = Pattern::regex_with(&prefix, false)?;
+            prefixes.push(Condition::Field(Marker::new(Field::File, pattern)));
+        }
+        Ok(Condition::Any(prefixes))
+    }
+
+    pub fn is_match(&self, srcloc: &SrcLoc) -> bool {
+        match self {
+            Condition::Field(marker) => marker.is_match(srcloc),
+            Condition::Line(range) => range.contains(&srcloc.line),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.is_match(srcloc)),
+            Condition::Not(condition) => !condition.is_match(srcloc),
+        }
+    }
+}
+
+/// Conditions that must all hold for `action` to apply.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub conditions: Vec<Condition>,
+    pub action: Action,
+    /// Where the rule came from, for reporting.
+    pub label: String,
+}
+
+impl Rule {
+    /// A rule matching a single marker, labelled with the marker itself.
+    pub fn marker(field: Field, pattern: Pattern, action: Action) -> Self {
+        let marker = Marker::new(field, pattern);
+        Self {
+            label: marker.to_string(),
+            conditions: vec![Condition::Field(marker)],
+            action,
+        }
+    }
+
+    /// A rule matching the srclocs of one file defined within a line range,
+    /// from `FILE:START-END` or `FILE:LINE`. `FILE` is the whole path, matched
+    /// case-insensitively.
+    pub fn line_range(spec: &str, action: Action) -> Result<Self> {
+        let (file, lines) = spec
+            .rsplit_once(':')
+            .with_context(|| format!("expected FILE:START-END, got {spec:?}"))?;
+        let file = Pattern::glob(&globset::escape(&normalize_path(file)))?;
+        Ok(Self {
+            conditions: vec![
+                Condition::Field(Marker::new(Field::File, file)),
+                Condition::Line(parse_line_range(lines)?),
+            ],
+            action,
+            label: format!("line range {spec}"),
+        })
+    }
+
+    /// A rule matching every srcloc for which `public` doesn't hold.
+    pub fn deny_unless(public: Condition, action: Action, label: String) -> Self {
+        Self {
+            conditions: vec![Condition::Not(Box::new(public))],
+            action,
+            label,
+        }
+    }
+
+    pub fn is_match(&self, srcloc: &SrcLoc) -> bool {
+        self.conditions.iter().all(|c| c.is_match(srcloc))
+    }
+}
+
+/// Outcome of checking a srcloc against [`Rules`].
+#[derive(Debug, Clone, Copy)]
+pub enum Verdict<'a> {
+    /// No rule matched.
+    Public,
+    /// `rule` was the first to match.
+    Matched { rule: &'a Rule },
+    /// A keep rule matched first, overriding the later `overridden` rule.
+    Excepted {
+        rule: &'a Rule,
+        overridden: &'a Rule,
+    },
+}
+
+impl Verdict<'_> {
+    /// The action to take: [`Action::Keep`] if no rule matched.
+    pub fn action(&self) -> Action {
+        match self {
+            Verdict::Public | Verdict::Excepted { .. } => Action::Keep,
+            Verdict::Matched { rule } => rule.action,
+        }
+    }
+}
+
+/// An ordered list of rules; the first matching rule decides a srcloc.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `rule`, which applies only if no earlier rule matches.
+    pub fn push(&mut self, rule: Rule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Append all of `other`'s rules.
+    pub fn extend(&mut self, other: Rules) -> &mut Self {
+        self.rules.extend(other.rules);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    pub fn check(&self, srcloc: &SrcLoc) -> Verdict<'_> {
+        let mut matching = self.rules.iter().filter(|r| r.is_match(srcloc));
+        let Some(rule) = matching.next() else {
+            return Verdict::Public;
+        };
+        if rule.action == Action::Keep
+            && let Some(overridden) = matching.find(|r| r.action != Action::Keep)
+        {
+            return Verdict::Excepted { rule, overridden };
+        }
+        Verdict::Matched { rule }
+    }
+
+    /// Load a TOML rules file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("reading rules file: {}", path.display()))?;
+        Self::from_toml(&text, &path.display().to_string())
+            .with_context(|| format!("loading rules file: {}", path.display()))
+    }
+
+    /// Parse a TOML rules document; `origin` names it in rule labels.
+    ///
+    /// ```toml
+    /// [[rule]]
+    /// action = "keep"                      # keep, redact, pseudonymize or drop
+    /// function = "glob:/datum/secretary/**"
+    ///
+    /// [[rule]]
+    /// action = "redact"
+    /// file = "code/modules/admin/foo.dm"   # substring, glob:<GLOB> or re:<REGEX>
+    /// line = "120-340"                     # or a single line number
+    /// case_sensitive = true                # default: false, true for regexes
+    /// ```
+    ///
+    /// A rule's conditions must all match; rules are tried in order.
+    pub fn from_toml(text: &str, origin: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(text)?;
+        Self::from_defs(file.rules, origin)
+    }
+
+    pub(crate) fn from_defs(defs: Vec<RuleDef>, origin: &str) -> Result<Self> {
+        let mut rules = Self::new();
+        for (i, def) in defs.into_iter().enumerate() {
+            let label = format!("rule #{} in {origin}", i + 1);
+            rules.push(def.into_rule(label.clone()).context(label)?);
+        }
+        Ok(rules)
+    }
+}
+
+/// Parse `N` or `START-END` (inclusive).
+pub fn parse_line_range(s: &str) -> Result<RangeInclusive<u32>> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u32>()
+            .with_context(|| format!("invalid line number {n:?}"))
+    };
+    let range = match s.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => {
+            let line = parse(s)?;
+            line..=line
+        }
+    };
+    if range.is_empty() {
+        bail!("empty line range {s:?}");
+    }
+    Ok(range)
+}
+
+// ---------------------------------------------------------------------------
+// TOML schema
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleDef>,
+}
+
+/// One `[[rule]]` table.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RuleDef {
+    action: Action,
+    name: Option<String>,
+    #[serde(alias = "fn")]
+    function: Option<String>,
+    file: Option<String>,
+    line: Option<LineDef>,
+    case_sensitive: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LineDef {
+    Single(u32),
+    Range(String),
+}
+
+impl RuleDef {
+    fn into_rule(self, label: String) -> Result<Rule> {
+        let mut conditions = Vec::new();
+        for (field, pattern) in [
+            (Field::Name, &self.name),
+            (Field::Function, &self.function),
+            (Field::File, &self.file),
+        ] {
+            if let Some(p) = pattern {
+                let pattern = Pattern::parse_with(p, self.case_sensitive)?;
+                conditions.push(Condition::Field(Marker::new(field, pattern)));
+            }
+        }
+        match self.line {
+            Some(LineDef::Single(line)) => conditions.push(Condition::Line(line..=line)),
+            Some(LineDef::Range(s)) => conditions.push(Condition::Line(parse_line_range(&s)?)),
+            None => {}
+        }
+        if conditions.is_empty() {
+            bail!("rule has no name, function, file or line condition");
+        }
+        Ok(Rule {
+            conditions,
+            action: self.action,
+            label,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> SrcLoc {
+        SrcLoc {
+            file: path.into(),
+            ..SrcLoc::default()
+        }
+    }
+
+    /// Whether a one-rule file with `file = pattern` (and `case_sensitive`,
+    /// if given) matches `path`.
+    fn matches(pattern: &str, case_sensitive: Option<bool>, path: &str) -> bool {
+        let mut text = format!("[[rule]]\naction = \"redact\"\nfile = {pattern:?}\n");
+        if let Some(case_sensitive) = case_sensitive {
+            text += &format!("case_sensitive = {case_sensitive}\n");
+        }
+        let rules = Rules::from_toml(&text, "test").unwrap();
+        rules.check(&file(path)).action() == Action::Redact
+    }
+
+    #[test]
+    fn case_sensitive_overrides_each_kind() {
+        let path = "Code_Secret/Plot.dm";
+        for (pattern, default) in [
+            ("code_secret", false),
+            ("glob:code_secret/*.dm", false),
+            ("re:^code_secret/", true),
+        ] {
+            assert_eq!(matches(pattern, None, path), !default, "{pattern}");
+            assert!(!matches(pattern, Some(true), path), "{pattern}");
+            assert!(matches(pattern, Some(false), path), "{pattern}");
+            assert!(
+                matches(pattern, Some(true), &path.to_lowercase()),
+                "{pattern}"
+            );
+        }
+    }
+
+    #[test]
+    fn line_range_specs() {
+        let at = |path: &str, line| SrcLoc { line, ..file(path) };
+
+        let rule = Rule::line_range(r"C:\code\foo.dm:120-340", Action::Redact).unwrap();
+        assert!(rule.is_match(&at(r"C:\code\foo.dm", 120)));
+        assert!(rule.is_match(&at("c:/CODE/Foo.dm", 340)));
+        assert!(!rule.is_match(&at(r"C:\code\foo.dm", 119)));
+        assert!(!rule.is_match(&at(r"C:\code\foo.dm", 341)));
+        assert!(!rule.is_match(&at(r"D:\code\foo.dm", 200)));
+
+        let rule = Rule::line_range("code/[x]/foo.dm:120", Action::Redact).unwrap();
+        assert!(rule.is_match(&at(r"code\[X]\foo.dm", 120)));
+        assert!(!rule.is_match(&at("code/[x]/foo.dm", 121)));
+        assert!(!rule.is_match(&at("code/x/foo.dm", 120)));
+
+        for spec in [
+            "foo.dm",
+            "foo.dm:",
+            "foo.dm:120-",
+            "foo.dm:-340",
+            "foo.dm:340-120",
+        ] {
+            assert!(Rule::line_range(spec, Action::Redact).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn parse_line_ranges() {
+        assert_eq!(parse_line_range("120").unwrap(), 120..=120);
+        assert_eq!(parse_line_range(" 120 - 340 ").unwrap(), 120..=340);
+        assert_eq!(parse_line_range("7-7").unwrap(), 7..=7);
+        for s in ["", "x", "120-", "-340", "340-120", "1-2-3"] {
+            assert!(parse_line_range(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn keep_rules_take_precedence() {
+        // In the order the binary pushes them: exceptions, markers, a
+        // codebase scan, then default-deny.
+        let mut rules = Rules::new();
+        rules
+            .push(Rule::marker(
+                Field::File,
+                Pattern::parse("public").unwrap(),
+                Action::Keep,
+            ))
+            .push(Rule::marker(
+                Field::File,
+                Pattern::substring("code_secret"),
+                Action::Redact,
+            ))
+            .push(Rule::marker(
+                Field::File,
+                Pattern::glob("+secret/**").unwrap(),
+                Action::Drop,
+            ))
+            .push(Rule::deny_unless(
+                Condition::file_under(&["code/".into()]).unwrap(),
+                Action::Redact,
+                "default deny".into(),
+            ));
+        let labels = |path: &str| match rules.check(&file(path)) {
+            Verdict::Public => (None, None),
+            Verdict::Matched { rule } => (Some(rule.label.clone()), None),
+            Verdict::Excepted { rule, overridden } => {
+                (Some(rule.label.clone()), Some(overridden.label.clone()))
+            }
+        };
+        let keep = Some("file \"public\"".to_owned());
+        let marker = Some("file \"code_secret\"".to_owned());
+        let codebase = Some("file glob:\"+secret/**\"".to_owned());
+        let deny = Some("default deny".to_owned());
+
+        assert_eq!(labels("code/x.dm"), (None, None));
+        assert_eq!(labels("code/public.dm"), (keep.clone(), None));
+        assert_eq!(labels("code/code_secret/x.dm"), (marker.clone(), None));
+        assert_eq!(labels("+secret/x.dm"), (codebase.clone(), None));
+        assert_eq!(labels("maps/x.dm"), (deny.clone(), None));
+        assert_eq!(labels("code/code_secret/public.dm"), (keep.clone(), marker));
+        assert_eq!(labels("+secret/public.dm"), (keep.clone(), codebase));
+        assert_eq!(labels("maps/public.dm"), (keep, deny));
+        assert_eq!(
+            rules.check(&file("+secret/public.dm")).action(),
+            Action::Keep
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, Result, anyhow, bail};
+
+use crate::header::Header;
+use crate::srcloc::SrcLoc;
+
+/// What to do with a single identifying field.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FieldPolicy<T> {
+    /// Leave the field as it is.
+    #[default]
+    Keep,
+    /// Clear the field (empty text or zero).
+    Blank,
+    /// Overwrite the field with a fixed value.
+    Replace(T),
+}
+
+impl<T: Default + Clone> FieldPolicy<T> {
+    /// The new value for a field currently holding `current`.
+    pub fn resolve(&self, current: &T) -> T {
+        match self {
+            FieldPolicy::Keep => current.clone(),
+            FieldPolicy::Blank => T::default(),
+            FieldPolicy::Replace(v) => v.clone(),
+        }
+    }
+}
+
+/// Parses `keep`, `blank` or `replace:<VALUE>`.
+impl<T> FromStr for FieldPolicy<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(FieldPolicy::Keep),
+            "blank" => Ok(FieldPolicy::Blank),
+            _ => match s.strip_prefix("replace:") {
+                Some(v) => {
+                    Ok(FieldPolicy::Replace(v.parse().map_err(|e| {
+                        anyhow!("invalid replacement value {v:?}: {e}")
+                    })?))
+                }
+                None => bail!("expected `keep`, `blank` or `replace:<VALUE>`, got {s:?}"),
+            },
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for FieldPolicy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldPolicy::Keep => f.write_str("keep"),
+            FieldPolicy::Blank => f.write_str("blank"),
+            FieldPolicy::Replace(v) => write!(f, "replace:{v}"),
+        }
+    }
+}
+
+/// Per-field scrubbing of the identifying parts of a [`Header`].
+///
+/// The default keeps every field.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderScrub {
+    /// OS, CPU and host name of the capturing machine.
+    pub host_info: FieldPolicy<String>,
+    /// Usually the full path to the DreamDaemon executable.
+    pub program_name: FieldPolicy<String>,
+    pub process_id: FieldPolicy<i64>,
+    /// Wall-clock capture start.
+    pub epoch: FieldPolicy<i64>,
+    /// Wall-clock program start.
+    pub exec_time: FieldPolicy<i64>,
+}
+
+impl HeaderScrub {
+    /// Blank every identifying field.
+    pub fn blank_all() -> Self {
+        Self {
+            host_info: FieldPolicy::Blank,
+            program_name: FieldPolicy::Blank,
+            process_id: FieldPolicy::Blank,
+            epoch: FieldPolicy::Blank,
+            exec_time: FieldPolicy::Blank,
+        }
+    }
+
+    /// Scrub `header` in place, returning the names of the fields that
+    /// changed.
+    pub fn apply(&self, header: &mut Header) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        // Text fields are rewritten whenever they aren't kept, so stale bytes
+        // after the NUL terminator are cleared too.
+        if self.host_info != FieldPolicy::Keep {
+            let before = header.host_info;
+            let host_info = self.host_info.resolve(&String::new());
+            header.set_host_info(&host_info);
+            if header.host_info != before {
+                changed.push("host info");
+            }
+        }
+
+        if self.program_name != FieldPolicy::Keep {
+            let before = header.program_name;
+            let program_name = self.program_name.resolve(&String::new());
+            header.set_program_name(&program_name);
+            if header.program_name != before {
+                changed.push("program name");
+            }
+        }
+
+        for (name, policy, field) in [
+            ("process id", &self.process_id, &mut header.process_id),
+            ("epoch", &self.epoch, &mut header.epoch),
+            ("exec time", &self.exec_time, &mut header.exec_time),
+        ] {
+            let value = policy.resolve(field);
+            if value != *field {
+                *field = value;
+                changed.push(name);
+            }
+        }
+
+        changed
+    }
+}
+
+/// What to keep of the numeric fields of a redacted srcloc.
+///
+/// The default blanks both, since the line pinpoints the proc in the secret
+/// file and a custom color can identify it.
+#[derive(Debug, Clone)]
+pub struct SrcLocScrub {
+    pub line: FieldPolicy<u32>,
+    pub color: FieldPolicy<u32>,
+}
+
+impl Default for SrcLocScrub {
+    fn default() -> Self {
+        Self {
+            line: FieldPolicy::Blank,
+            color: FieldPolicy::Blank,
+        }
+    }
+}
+
+impl SrcLocScrub {
+    /// Scrub the line and color of a redacted `srcloc` in place.
+    pub fn apply(&self, srcloc: &mut SrcLoc) {
+        srcloc.line = self.line.resolve(&srcloc.line);
+        srcloc.color = self.color.resolve(&srcloc.color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{FILE_SIGNATURE, HEADER_SIZE, PROGRAM_NAME_LEN};
+
+    fn header() -> Header {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&FILE_SIGNATURE.to_le_bytes());
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let mut header = Header::from_bytes(&bytes).unwrap();
+        header.set_host_info("Windows 10, buildhost");
+        header.set_program_name(r"C:\BYOND\bin\dreamdaemon.exe");
+        header.process_id = 4242;
+        header.epoch = 1_700_000_000;
+        header.exec_time = 1_699_999_000;
+        header
+    }
+
+    #[test]
+    fn field_policy_parsing() {
+        assert_eq!(
+            "keep".parse::<FieldPolicy<i64>>().unwrap(),
+            FieldPolicy::Keep
+        );
+        assert_eq!(
+            "blank".parse::<FieldPolicy<i64>>().unwrap(),
+            FieldPolicy::Blank
+        );
+        assert_eq!(
+            "replace:-5".parse::<FieldPolicy<i64>>().unwrap(),
+            FieldPolicy::Replace(-5)
+        );
+        assert_eq!(
+            "replace:".parse::<FieldPolicy<String>>().unwrap(),
+            FieldPolicy::Replace(String::new())
+        );
+        assert_eq!(
+            "replace:a:b".parse::<FieldPolicy<String>>().unwrap(),
+            FieldPolicy::Replace("a:b".to_owned())
+        );
+        for bad in ["", "Keep", "zero", "replace", "replace:x", "replace: 1"] {
+            assert!(bad.parse::<FieldPolicy<i64>>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn header_scrub_reports_changed_fields() {
+        let mut scrubbed = header();
+        assert!(HeaderScrub::default().apply(&mut scrubbed).is_empty());
+        assert_eq!(scrubbed, header());
+
+        let changed = HeaderScrub::blank_all().apply(&mut scrubbed);
+        assert_eq!(
+            changed,
+            [
+                "host info",
+                "program name",
+                "process id",
+                "epoch",
+                "exec time"
+            ]
+        );
+        assert_eq!(scrubbed.host_info_str(), "");
+        assert_eq!((scrubbed.process_id, scrubbed.epoch), (0, 0));
+
+        // Already blank, so nothing changes the second time.
+        assert!(HeaderScrub::blank_all().apply(&mut scrubbed).is_empty());
+
+        let scrub = HeaderScrub {
+            program_name: FieldPolicy::Replace("dreamdaemon".to_owned()),
+            epoch: FieldPolicy::Replace(1_700_000_000),
+            ..HeaderScrub::default()
+        };
+        let mut scrubbed = header();
+        assert_eq!(scrub.apply(&mut scrubbed), ["program name"]);
+        assert_eq!(scrubbed.program_name_str(), "dreamdaemon");
+        assert_eq!(scrubbed.host_info_str(), "Windows 10, buildhost");
+    }
+
+    #[test]
+    fn text_replacements_truncate() {
+        let long = "é".repeat(PROGRAM_NAME_LEN);
+        let scrub = HeaderScrub {
+            program_name: FieldPolicy::Replace(long.clone()),
+            ..HeaderScrub::default()
+        };
+        let mut scrubbed = header();
+        assert_eq!(scrub.apply(&mut scrubbed), ["program name"]);
+        let name = scrubbed.program_name_str();
+        assert!(name.len() < PROGRAM_NAME_LEN, "{}", name.len());
+        assert!(long.starts_with(name.as_ref()));
+    }
+
+    #[test]
+    fn srcloc_scrub() {
+        let loc = SrcLoc {
+            name: "plan".into(),
+            function: "/datum/secret/proc/plan".into(),
+            file: "code_secret/plan.dm".into(),
+            line: 120,
+            color: 0x00AB_CDEF,
+        };
+
+        let mut scrubbed = loc.clone();
+        SrcLocScrub::default().apply(&mut scrubbed);
+        assert_eq!((scrubbed.line, scrubbed.color), (0, 0));
+        assert_eq!(scrubbed.function, loc.function);
+
+        let mut scrubbed = loc.clone();
+        SrcLocScrub {
+            line: FieldPolicy::Keep,
+            color: FieldPolicy::Replace(0x00FF_0000),
+        }
+        .apply(&mut scrubbed);
+        assert_eq!((scrubbed.line, scrubbed.color), (120, 0x00FF_0000));
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{BufReader, Read, Write};
+use std::iter;
+
+use age::secrecy::SecretString;
+use anyhow::{Context, Result, bail};
+
+use crate::event::Event;
+use crate::header::Header;
+use crate::io::{read_u32, read_u64};
+use crate::redact::Disposition;
+use crate::srcloc::{self, SrcLoc};
+use crate::version::Version;
+
+/// `"utracysc"` as a little-endian `u64`.
+pub const SIDECAR_SIGNATURE: u64 = 0x6373796361727475;
+pub const SIDECAR_VERSION: u32 = 2;
+
+/// Everything needed to restore a redacted `.utracy` file to the original,
+/// byte for byte.
+///
+/// Written next to the public file, encrypted with [age](https://age-encryption.org)
+/// to a passphrase or to public keys, so people with the key can recover what
+/// was redacted without the original capture. See [`crate::unredact`].
+///
+/// Plaintext layout (all integers LE): signature (`u64`), version (`u32`),
+/// SHA-256 of the original file (32 bytes), original header
+/// (1200 bytes), original srcloc count (`u32`), then three counted lists:
+///
+/// - redacted srclocs (`u32` count): input index (`u32`) + disposition
+///   (`u8`) + srcloc
+/// - dropped events (`u64` count): output position (`u64`) + event
+/// - merged zones (`u64` count): input srcloc index (`u32`)
+///
+/// Srclocs and events use the latest `.utracy` encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sidecar {
+    /// SHA-256 of the original file.
+    pub checksum: [u8; 32],
+    /// The original, unscrubbed header.
+    pub header: Header,
+    /// Number of srclocs in the original table.
+    pub srcloc_count: u32,
+    /// `(input srcloc index, disposition, original srcloc)`, in table order.
+    pub entries: Vec<(u32, Disposition, SrcLoc)>,
+    /// Events left out of the output, in input order, keyed by the number
+    /// of output events that precede them.
+    pub dropped_events: Vec<(u64, Event)>,
+    /// For [`Disposition::Merged`] entries, the input srcloc of every zone begin that points
+    /// at the shared entry, in stream order.
+    pub merged_zones: Vec<u32>,
+}
+
+/// Who can decrypt a sidecar.
+pub enum SidecarKey {
+    Passphrase(SecretString),
+    Recipients(Vec<age::x25519::Recipient>),
+}
+
+impl Sidecar {
+    /// The original srcloc at input index `index`, if it was redacted.
+    pub fn get(&self, index: u32) -> Option<&SrcLoc> {
+        self.entries
+            .iter()
+            .find(|(i, _, _)| *i == index)
+            .map(|(_, _, loc)| loc)
+    }
+
+    /// Write the unencrypted sidecar to `w`.
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&SIDECAR_SIGNATURE.to_le_bytes())
+            .context("writing sidecar signature")?;
+        w.write_all(&SIDECAR_VERSION.to_le_bytes())
+            .context("writing sidecar version")?;
+        w.write_all(&self.checksum)
+            .context("writing sidecar checksum")?;
+        self.header.write(w)?;
+        srcloc::write_count(w, self.srcloc_count)?;
+
+        w.write_all(&(self.entries.len() as u32).to_le_bytes())
+            .context("writing sidecar entry count")?;
+        for (index, disposition, loc) in &self.entries {
+            w.write_all(&index.to_le_bytes())
+                .context("writing sidecar entry index")?;
+            w.write_all(&[disposition_to_u8(*disposition)])
+                .context("writing sidecar entry disposition")?;
+            loc.write(w, Version::LATEST)?;
+        }
+
+        w.write_all(&(self.dropped_events.len() as u64).to_le_bytes())
+            .context("writing sidecar dropped event count")?;
+        for (pos, event) in &self.dropped_events {
+            w.write_all(&pos.to_le_bytes())
+                .context("writing sidecar dropped event position")?;
+            event.write(w, Version::LATEST)?;
+        }
+
+        w.write_all(&(self.merged_zones.len() as u64).to_le_bytes())
+            .context("writing sidecar merged zone count")?;
+        for index in &self.merged_zones {
+            w.write_all(&index.to_le_bytes())
+                .context("writing sidecar merged zone")?;
+        }
+        Ok(())
+    }
+
+    /// Read an unencrypted sidecar from `r`.
+    pub fn read<R: Read>(r: &mut R) -> Result<Self> {
+        let sig = read_u64(r).context("reading sidecar signature")?;
+        if sig != SIDECAR_SIGNATURE {
+            bail!(
+                "invalid sidecar signature: got 0x{sig:016X}, expected 0x{SIDECAR_SIGNATURE:016X}"
+            );
+        }
+        let ver = read_u32(r).context("reading sidecar version")?;
+        if ver != SIDECAR_VERSION {
+            bail!("unsupported sidecar version: got {ver}, expected {SIDECAR_VERSION}");
+        }
+
+        let mut checksum = [0u8; 32];
+        r.read_exact(&mut checksum)
+            .context("reading sidecar checksum")?;
+        let header = Header::read(r).context("reading sidecar header")?;
+        let srcloc_count = srcloc::read_count(r)?;
+
+        let count = read_u32(r).context("reading sidecar entry count")?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let index = read_u32(r).context("reading sidecar entry index")?;
+            let mut disposition = [0u8; 1];
+            r.read_exact(&mut disposition)
+                .context("reading sidecar entry disposition")?;
+            let disposition = disposition_from_u8(disposition[0])?;
+            entries.push((index, disposition, SrcLoc::read(r, Version::LATEST)?));
+        }
+
+        let count = read_u64(r).context("reading sidecar dropped event count")?;
+        let mut dropped_events = Vec::new();
+        for _ in 0..count {
+            let pos = read_u64(r).context("reading sidecar dropped event position")?;
+            let event = Event::read(r, Version::LATEST)?
+                .context("sidecar ends inside the dropped event list")?;
+            dropped_events.push((pos, event));
+        }
+
+        let count = read_u64(r).context("reading sidecar merged zone count")?;
+        let mut merged_zones = Vec::new();
+        for _ in 0..count {
+            merged_zones.push(read_u32(r).context("reading sidecar merged zone")?);
+        }
+
+        Ok(Self {
+            checksum,
+            header,
+            srcloc_count,
+            entries,
+            dropped_events,
+            merged_zones,
+        })
+    }
+
+    /// Encrypt the sidecar for `key` and write it to `w`.
+    pub fn write_encrypted<W: Write>(&self, w: W, key: &SidecarKey) -> Result<()> {
+        let encryptor = match key {
+            SidecarKey::Passphrase(pass) => age::Encryptor::with_user_passphrase(pass.clone()),
+            SidecarKey::Recipients(recipients) => {
+                age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+                    .context("setting up sidecar encryption")?
+            }
+        };
+        let mut out = encryptor
+            .wrap_output(w)
+            .context("writing sidecar encryption header")?;
+        self.write(&mut out)?;
+        out.finish()
+            .and_then(|mut w| w.flush())
+            .context("finishing sidecar encryption")
+    }
+
+    /// Decrypt a sidecar with a passphrase.
+    pub fn read_with_passphrase<R: Read>(r: R, passphrase: SecretString) -> Result<Self> {
+        let identity = age::scrypt::Identity::new(passphrase);
+        Self::read_encrypted(r, iter::once(&identity as &dyn age::Identity))
+    }
+
+    /// Decrypt a sidecar with any of `identities` (e.g. from an age identity
+    /// file).
+    pub fn read_with_identities<R: Read>(
+        r: R,
+        identities: &[Box<dyn age::Identity>],
+    ) -> Result<Self> {
+        Self::read_encrypted(r, identities.iter().map(|i| i.as_ref()))
+    }
+
+    fn read_encrypted<'a, R: Read>(
+        r: R,
+        identities: impl Iterator<Item = &'a dyn age::Identity>,
+    ) -> Result<Self> {
+        let decryptor = age::Decryptor::new(BufReader::new(r)).context("reading sidecar header")?;
+        let mut plain = decryptor
+            .decrypt(identities)
+            .context("decrypting sidecar")?;
+        Self::read(&mut plain)
+    }
+}
+
+fn disposition_to_u8(disposition: Disposition) -> u8 {
+    match disposition {
+        Disposition::Placeholder => 0,
+        Disposition::Dropped => 1,
+        Disposition::Merged => 2,
+    }
+}
+
+fn disposition_from_u8(b: u8) -> Result<Disposition> {
+    match b {
+        0 => Ok(Disposition::Placeholder),
+        1 => Ok(Disposition::Dropped),
+        2 => Ok(Disposition::Merged),
+        other => bail!("unknown sidecar entry disposition {other}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact::tests::{capture, rules, run};
+    use crate::redact::{Mode, Options};
+
+    fn sample() -> Sidecar {
+        let options = Options {
+            rules: rules(),
+            mode: Mode::Merge,
+            sidecar: true,
+            ..Options::default()
+        };
+        let (report, _) = run(&capture(Version::V2), &options);
+        report.sidecar.unwrap()
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let sidecar = sample();
+        assert!(!sidecar.entries.is_empty());
+        assert!(!sidecar.dropped_events.is_empty());
+        assert!(!sidecar.merged_zones.is_empty());
+
+        let identity = age::x25519::Identity::generate();
+        let key = SidecarKey::Recipients(vec![identity.to_public()]);
+        let mut bytes = Vec::new();
+        sidecar.write_encrypted(&mut bytes, &key).unwrap();
+
+        let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(identity)];
+        let read = Sidecar::read_with_identities(bytes.as_slice(), &identities).unwrap();
+        assert_eq!(read, sidecar);
+
+        let other: Vec<Box<dyn age::Identity>> = vec![Box::new(age::x25519::Identity::generate())];
+        assert!(Sidecar::read_with_identities(bytes.as_slice(), &other).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_sidecar() {
+        let mut bytes = Vec::new();
+        sample().write(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Sidecar::read(&mut bytes.as_slice()).is_err());
+    }
+}
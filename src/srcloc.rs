@@ -0,0 +1,124 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+
+use crate::io::{read_lenpfx_string, read_u32, write_lenpfx_string};
+use crate::version::Version;
+
+/// A source location entry from the srcloc table.
+///
+/// Events refer to srclocs by their index in the table.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SrcLoc {
+    /// Zone name shown by Tracy.
+    pub name: String,
+    /// Proc path, e.g. `/datum/foo/proc/bar`.
+    pub function: String,
+    /// Source file path, e.g. `code/modules/foo/bar.dm`.
+    pub file: String,
+    pub line: u32,
+    /// Zone color; always 0 in formats without srcloc colors.
+    pub color: u32,
+}
+
+impl SrcLoc {
+    /// Read a single srcloc entry encoded as `version` from `r`.
+    pub fn read<R: Read>(r: &mut R, version: Version) -> Result<Self> {
+        let name = read_lenpfx_string(r).context("reading srcloc.name")?;
+        let function = read_lenpfx_string(r).context("reading srcloc.function")?;
+        let file = read_lenpfx_string(r).context("reading srcloc.file")?;
+        let line = read_u32(r).context("reading srcloc.line")?;
+        let color = if version.has_srcloc_color() {
+            read_u32(r).context("reading srcloc.color")?
+        } else {
+            0
+        };
+        Ok(Self {
+            name,
+            function,
+            file,
+            line,
+            color,
+        })
+    }
+
+    /// Write this srcloc entry to `w`, encoded as `version`.
+    ///
+    /// The color is dropped for formats without srcloc colors.
+    pub fn write<W: Write>(&self, w: &mut W, version: Version) -> Result<()> {
+        write_lenpfx_string(w, &self.name).context("writing srcloc.name")?;
+        write_lenpfx_string(w, &self.function).context("writing srcloc.function")?;
+        write_lenpfx_string(w, &self.file).context("writing srcloc.file")?;
+        w.write_all(&self.line.to_le_bytes())
+            .context("writing srcloc.line")?;
+        if version.has_srcloc_color() {
+            w.write_all(&self.color.to_le_bytes())
+                .context("writing srcloc.color")?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the `u32` LE srcloc count that precedes the srcloc table.
+pub fn read_count<R: Read>(r: &mut R) -> Result<u32> {
+    read_u32(r).context("reading srcloc_count")
+}
+
+/// Write the `u32` LE srcloc count that precedes the srcloc table.
+pub fn write_count<W: Write>(w: &mut W, count: u32) -> Result<()> {
+    w.write_all(&count.to_le_bytes())
+        .context("writing srcloc_count")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SrcLoc {
+        SrcLoc {
+            name: "Life".into(),
+            function: "/mob/living/proc/Life".into(),
+            file: "code/modules/mob/living/life.dm".into(),
+            line: 12,
+            color: 0x00FF_0000,
+        }
+    }
+
+    #[test]
+    fn v2_round_trip() {
+        let mut bytes = Vec::new();
+        sample().write(&mut bytes, Version::V2).unwrap();
+        let read = SrcLoc::read(&mut bytes.as_slice(), Version::V2).unwrap();
+        assert_eq!(read, sample());
+    }
+
+    #[test]
+    fn v2_layout() {
+        let loc = SrcLoc {
+            name: "a".into(),
+            function: "bc".into(),
+            file: String::new(),
+            line: 7,
+            color: 0x0102_0304,
+        };
+        let mut bytes = Vec::new();
+        loc.write(&mut bytes, Version::V2).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                1, 0, 0, 0, b'a', //
+                2, 0, 0, 0, b'b', b'c', //
+                0, 0, 0, 0, //
+                7, 0, 0, 0, //
+                4, 3, 2, 1,
+            ]
+        );
+    }
+
+    #[test]
+    fn count_round_trip() {
+        let mut bytes = Vec::new();
+        write_count(&mut bytes, 0xDEAD_BEEF).unwrap();
+        assert_eq!(read_count(&mut bytes.as_slice()).unwrap(), 0xDEAD_BEEF);
+    }
+}
@@ -0,0 +1,240 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result, bail};
+
+use crate::event::{Event, EventReader, EventWriter};
+use crate::header::Header;
+use crate::io::HashingWriter;
+use crate::redact::Disposition;
+use crate::sidecar::Sidecar;
+use crate::srcloc::{self, SrcLoc};
+
+/// Where an output srcloc came from.
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    /// A kept or placeholder entry for this input index.
+    Input(u32),
+    /// The shared entry of [`Disposition::Merged`] srclocs.
+    Merged,
+}
+
+/// Rebuild the original .utracy file from a redacted one and the matching
+/// [`Sidecar`], writing it to `writer`.
+///
+/// Fails if the result doesn't match the checksum stored in the sidecar;
+/// `writer` will have received the mismatching output by then.
+pub fn process<R: Read, W: Write>(reader: &mut R, writer: &mut W, sidecar: &Sidecar) -> Result<()> {
+    let mut writer = HashingWriter::new(writer);
+
+    // -- Header --------------------------------------------------------------
+    // The redacted header may be scrubbed; the sidecar holds the original.
+    let redacted_header = Header::read(reader)?;
+    if redacted_header.version != sidecar.header.version {
+        bail!(
+            "sidecar is for a version {} file, but the input is version {}",
+            sidecar.header.version,
+            redacted_header.version
+        );
+    }
+    let version = sidecar.header.format_version()?;
+    sidecar.header.write(&mut writer)?;
+
+    // -- Srcloc table --------------------------------------------------------
+    let out_count = srcloc::read_count(reader)?;
+    let mut out_table = Vec::new();
+    for _ in 0..out_count {
+        out_table.push(SrcLoc::read(reader, version)?);
+    }
+    let mut out_table = out_table.into_iter();
+
+    let mismatch = || anyhow::anyhow!("srcloc table doesn't match the sidecar");
+    let mut slots = Vec::new();
+    let mut entries = sidecar.entries.iter().peekable();
+    let mut merged_seen = false;
+
+    srcloc::write_count(&mut writer, sidecar.srcloc_count)?;
+    for index in 0..sidecar.srcloc_count {
+        let loc = match entries.next_if(|(i, _, _)| *i == index) {
+            Some((_, disposition, original)) => {
+                // Placeholder entries stay in place; merged ones share one
+                // entry where the first of them was.
+                match disposition {
+                    Disposition::Placeholder => {
+                        out_table.next().ok_or_else(mismatch)?;
+                        slots.push(Slot::Input(index));
+                    }
+                    Disposition::Merged if !merged_seen => {
+                        out_table.next().ok_or_else(mismatch)?;
+                        slots.push(Slot::Merged);
+                        merged_seen = true;
+                    }
+                    Disposition::Merged | Disposition::Dropped => {}
+                }
+                original.clone()
+            }
+            None => {
+                slots.push(Slot::Input(index));
+                out_table.next().ok_or_else(mismatch)?
+            }
+        };
+        loc.write(&mut writer, version)?;
+    }
+    if entries.next().is_some() || out_table.next().is_some() {
+        return Err(mismatch());
+    }
+
+    // -- Event stream --------------------------------------------------------
+    // Redaction copies the stream untouched unless it renumbered or dropped
+    // zones, so the same goes for restoring it.
+    let untouched = sidecar.dropped_events.is_empty()
+        && sidecar
+            .entries
+            .iter()
+            .all(|(_, disposition, _)| *disposition == Disposition::Placeholder);
+    if untouched {
+        std::io::copy(reader, &mut writer).context("copying event stream")?;
+    } else {
+        let mut events = EventReader::new(reader, version);
+        let mut out = EventWriter::new(&mut writer, version);
+        let mut dropped = sidecar.dropped_events.iter().peekable();
+        let mut merged = sidecar.merged_zones.iter();
+        let mut position = 0;
+
+        loop {
+            while let Some((_, event)) = dropped.next_if(|(pos, _)| *pos == position) {
+                out.write(event)?;
+            }
+            let Some(event) = events.next() else {
+                break;
+            };
+
+            let event = match event? {
+                Event::ZoneBegin {
+                    tid,
+                    srcloc,
+                    timestamp,
+                } => {
+                    let srcloc = match slots.get(srcloc as usize) {
+                        Some(Slot::Input(index)) => *index,
+                        Some(Slot::Merged) => *merged
+                            .next()
+                            .context("more merged zones than recorded in the sidecar")?,
+                        None => bail!("event #{position} references unknown srcloc {srcloc}"),
+                    };
+                    Event::ZoneBegin {
+                        tid,
+                        srcloc,
+                        timestamp,
+                    }
+                }
+                other => other,
+            };
+            out.write(&event)?;
+            position += 1;
+        }
+        if dropped.next().is_some() {
+            bail!("sidecar has dropped events past the end of the input");
+        }
+    }
+
+    writer.flush().context("flushing output")?;
+    if writer.finish() != sidecar.checksum {
+        bail!("restored file does not match the original checksum");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redact::tests::{capture, rules, run, secret_files};
+    use crate::redact::{Mode, Options, ZonePolicy};
+    use crate::scrub::{FieldPolicy, HeaderScrub, SrcLocScrub};
+    use crate::version::Version;
+
+    #[test]
+    fn restores_every_mode_byte_for_byte() {
+        for version in Version::SUPPORTED.iter().copied() {
+            let input = capture(version);
+            for mode in [Mode::Placeholder, Mode::Drop, Mode::Merge] {
+                for zones in [ZonePolicy::Keep, ZonePolicy::Drop, ZonePolicy::DropTree] {
+                    let options = Options {
+                        rules: rules(),
+                        mode,
+                        zones,
+                        header: HeaderScrub::blank_all(),
+                        sidecar: true,
+                        ..Options::default()
+                    };
+                    let case = format!("v{version} {mode} {zones:?}");
+                    let (report, redacted) = run(&input, &options);
+                    assert_ne!(redacted, input, "{case}");
+
+                    // Through the serialized form, as the binary does.
+                    let mut bytes = Vec::new();
+                    report.sidecar.unwrap().write(&mut bytes).unwrap();
+                    let sidecar = Sidecar::read(&mut bytes.as_slice()).unwrap();
+
+                    let mut restored = Vec::new();
+                    process(&mut redacted.as_slice(), &mut restored, &sidecar)
+                        .unwrap_or_else(|e| panic!("{case}: {e:#}"));
+                    assert_eq!(restored, input, "{case}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn restores_undecoded_stream() {
+        let mut input = capture(Version::V2);
+        input.extend([0xEE; 23]);
+        let options = Options {
+            rules: secret_files(),
+            srcloc: SrcLocScrub {
+                color: FieldPolicy::Keep,
+                ..SrcLocScrub::default()
+            },
+            sidecar: true,
+            ..Options::default()
+        };
+        let (report, redacted) = run(&input, &options);
+        assert_eq!(report.events, None);
+
+        let mut restored = Vec::new();
+        process(
+            &mut redacted.as_slice(),
+            &mut restored,
+            &report.sidecar.unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored, input);
+    }
+
+    #[test]
+    fn rejects_mismatched_sidecar() {
+        let input = capture(Version::V2);
+        let options = Options {
+            rules: rules(),
+            sidecar: true,
+            ..Options::default()
+        };
+        let (report, redacted) = run(&input, &options);
+        let mut sidecar = report.sidecar.unwrap();
+        sidecar.entries[0].2.line += 1;
+        assert!(process(&mut redacted.as_slice(), &mut Vec::new(), &sidecar).is_err());
+    }
+
+    #[test]
+    fn huge_srcloc_count_is_an_error() {
+        let input = capture(Version::V2);
+        let options = Options {
+            sidecar: true,
+            ..Options::default()
+        };
+        let (report, mut redacted) = run(&input, &options);
+        redacted.truncate(crate::header::HEADER_SIZE);
+        redacted.extend(u32::MAX.to_le_bytes());
+        let sidecar = report.sidecar.unwrap();
+        assert!(process(&mut redacted.as_slice(), &mut Vec::new(), &sidecar).is_err());
+    }
+}
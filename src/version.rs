@@ -0,0 +1,62 @@
+use std::fmt;
+
+use anyhow::{Result, bail};
+
+/// A `.utracy` format revision.
+///
+/// Only [`Version::V2`], the revision the original tool was written
+/// against, is supported: srclocs carry a `color` field and the stream may
+/// hold [`Event::ZoneColor`]. Other revisions are rejected rather than
+/// parsed with a guessed layout; the `has_*` checks are where one would
+/// differ.
+///
+/// [`Event::ZoneColor`]: crate::Event::ZoneColor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Version {
+    V2,
+}
+
+impl Version {
+    /// Every revision this crate can read and write, oldest first.
+    pub const SUPPORTED: &[Version] = &[Version::V2];
+    /// The newest supported revision.
+    pub const LATEST: Version = Version::V2;
+
+    /// Look up the revision for the header `version` field.
+    pub fn from_u32(ver: u32) -> Result<Self> {
+        match Self::SUPPORTED.iter().find(|v| v.as_u32() == ver) {
+            Some(&v) => Ok(v),
+            None => bail!(
+                "unsupported .utracy version: got {ver}, expected one of {}",
+                Self::SUPPORTED
+                    .iter()
+                    .map(|v| v.as_u32().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// The value stored in the header `version` field.
+    pub fn as_u32(self) -> u32 {
+        match self {
+            Version::V2 => 2,
+        }
+    }
+
+    /// Whether srcloc entries carry a `color` field.
+    pub fn has_srcloc_color(self) -> bool {
+        self >= Version::V2
+    }
+
+    /// Whether the event stream may contain zone color events.
+    pub fn has_zone_color(self) -> bool {
+        self >= Version::V2
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_u32())
+    }
+}